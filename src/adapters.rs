@@ -0,0 +1,237 @@
+use crate::def::{Generator, ProgResult, RunningGenerator, Sized, System};
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+
+// Iterator over the programs of a generator and their weights.
+pub struct GeneratorIter<G, P, W> {
+    generator: G,
+    program: PhantomData<(P, W)>,
+}
+
+impl<G, P, W> GeneratorIter<G, P, W> {
+    pub fn new(generator: G) -> Self {
+        GeneratorIter {
+            generator,
+            program: PhantomData,
+        }
+    }
+}
+
+impl<P, W, G: Generator<P, W>> Iterator for GeneratorIter<G, P, W> {
+    type Item = (P, W);
+
+    fn next(&mut self) -> Option<(P, W)> {
+        self.generator.next()
+    }
+}
+
+// The programs of a generator with the sizes in a range, see `Generator::sizes`.
+pub struct Sizes<G> {
+    generator: G,
+    range: RangeInclusive<usize>,
+    // Whether the generator restricts the weights to the range itself.
+    restricted: bool,
+    done: bool,
+}
+
+impl<G> Sizes<G> {
+    pub fn new<P, W>(mut generator: G, range: RangeInclusive<usize>) -> Self
+    where
+        G: Generator<P, W>,
+    {
+        let restricted = generator.restrict_sizes(&range);
+        Sizes {
+            generator,
+            range,
+            restricted,
+            done: false,
+        }
+    }
+
+    // Whether to return the program. Marks the end at the first larger program.
+    fn contains<P: Sized>(&mut self, program: &P) -> bool {
+        if program.size() > *self.range.end() {
+            self.done = true;
+        }
+        !self.done && (self.restricted || self.range.contains(&program.size()))
+    }
+}
+
+impl<P: Sized, W, G: Generator<P, W>> Generator<P, W> for Sizes<G> {
+    fn raise_max_steps(&mut self, max_steps: usize) {
+        self.generator.raise_max_steps(max_steps);
+    }
+
+    fn next(&mut self) -> Option<(P, W)> {
+        while !self.done {
+            let (program, weight) = self.generator.next()?;
+            if self.contains(&program) {
+                return Some((program, weight));
+            }
+        }
+        None
+    }
+}
+
+impl<P: Sized, O, W, G: RunningGenerator<P, O, W>> RunningGenerator<P, O, W> for Sizes<G> {
+    fn next_run(&mut self) -> Option<(P, ProgResult<O>, W)> {
+        while !self.done {
+            let (program, result, weight) = self.generator.next_run()?;
+            if self.contains(&program) {
+                return Some((program, result, weight));
+            }
+        }
+        None
+    }
+}
+
+// The programs of a generator after the first n.
+pub struct SkipPrograms<G> {
+    generator: G,
+    skip: usize,
+}
+
+impl<G> SkipPrograms<G> {
+    pub fn new(generator: G, skip: usize) -> Self {
+        SkipPrograms { generator, skip }
+    }
+}
+
+impl<P, W, G: Generator<P, W>> Generator<P, W> for SkipPrograms<G> {
+    fn raise_max_steps(&mut self, max_steps: usize) {
+        self.generator.raise_max_steps(max_steps);
+    }
+
+    fn restrict_sizes(&mut self, sizes: &RangeInclusive<usize>) -> bool {
+        self.generator.restrict_sizes(sizes)
+    }
+
+    fn next(&mut self) -> Option<(P, W)> {
+        while self.skip > 0 {
+            self.skip -= 1;
+            self.generator.next()?;
+        }
+        self.generator.next()
+    }
+}
+
+impl<P, O, W, G: RunningGenerator<P, O, W>> RunningGenerator<P, O, W> for SkipPrograms<G> {
+    fn next_run(&mut self) -> Option<(P, ProgResult<O>, W)> {
+        while self.skip > 0 {
+            self.skip -= 1;
+            self.generator.next_run()?;
+        }
+        self.generator.next_run()
+    }
+}
+
+// Runs the programs of a generator that doesn't run them itself.
+pub struct Executed<'a, C, G> {
+    comp: &'a C,
+    generator: G,
+    max_steps: usize,
+}
+
+impl<'a, C, G> Executed<'a, C, G> {
+    pub fn new(comp: &'a C, generator: G) -> Self {
+        Executed {
+            comp,
+            generator,
+            max_steps: 0,
+        }
+    }
+}
+
+impl<C: System, W, G: Generator<C::Program, W>> Generator<C::Program, W> for Executed<'_, C, G> {
+    fn raise_max_steps(&mut self, max_steps: usize) {
+        self.max_steps = self.max_steps.max(max_steps);
+        self.generator.raise_max_steps(max_steps);
+    }
+
+    fn restrict_sizes(&mut self, sizes: &RangeInclusive<usize>) -> bool {
+        self.generator.restrict_sizes(sizes)
+    }
+
+    fn next(&mut self) -> Option<(C::Program, W)> {
+        self.generator.next()
+    }
+}
+
+impl<C: System, W, G: Generator<C::Program, W>> RunningGenerator<C::Program, C::Output, W>
+    for Executed<'_, C, G>
+{
+    fn next_run(&mut self) -> Option<(C::Program, ProgResult<C::Output>, W)> {
+        let (program, weight) = self.generator.next()?;
+        let result = self.comp.execute(&program, self.max_steps);
+        Some((program, result, weight))
+    }
+}
+
+// Takes the programs from several generators in turn, until all of them are exhausted.
+pub struct Interleave<G> {
+    generators: Vec<G>,
+    current: usize,
+}
+
+impl<G> Interleave<G> {
+    pub fn new(generators: Vec<G>) -> Self {
+        Interleave {
+            generators,
+            current: 0,
+        }
+    }
+}
+
+impl<P, W, G: Generator<P, W>> Generator<P, W> for Interleave<G> {
+    fn raise_max_steps(&mut self, max_steps: usize) {
+        for generator in self.generators.iter_mut() {
+            generator.raise_max_steps(max_steps);
+        }
+    }
+
+    fn next(&mut self) -> Option<(P, W)> {
+        while !self.generators.is_empty() {
+            if self.current >= self.generators.len() {
+                self.current = 0;
+            }
+            match self.generators[self.current].next() {
+                Some(item) => {
+                    self.current += 1;
+                    return Some(item);
+                }
+                None => {
+                    self.generators.remove(self.current);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Interleave;
+    use crate::brainfuck::BfNaiveGenerator;
+    use crate::def::{Generator, Sized};
+
+    #[test]
+    fn adapters() {
+        let all: Vec<_> = BfNaiveGenerator::new(3, false, true).iter().collect();
+
+        let sizes: Vec<_> = BfNaiveGenerator::new(5, false, true).sizes(2..=3).iter().collect();
+        assert!(sizes.iter().all(|(p, _)| (2..=3).contains(&p.size())));
+        assert_eq!(sizes.len(), all.iter().filter(|(p, _)| p.size() >= 2).count());
+
+        let skipped: Vec<_> =
+            BfNaiveGenerator::new(3, false, true).skip_programs(7).iter().collect();
+        assert_eq!(skipped.len(), all.len() - 7);
+        assert_eq!(skipped[0].0, all[7].0);
+
+        let gens = (1..=2).map(|size| BfNaiveGenerator::new(size, false, true).sizes(size..=size));
+        let interleaved: Vec<_> = Interleave::new(gens.collect()).iter().collect();
+        assert_eq!(interleaved[0].0.size(), 1);
+        assert_eq!(interleaved[1].0.size(), 2);
+        assert_eq!(interleaved[2].0.size(), 1);
+        assert_eq!(interleaved.len(), all.iter().filter(|(p, _)| p.size() <= 2).count());
+    }
+}
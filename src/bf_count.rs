@@ -1,19 +1,33 @@
+use crate::bit_tape::BitTape;
 use crate::brainfuck::{
-    BfNaiveGenerator, BfRawInstruction, BfSource,
+    spend, BfCanonicalGenerator, BfCompiled, BfCycleDetector, BfIncremental, BfLinearLoop,
+    BfRanking, BfRawInstruction, BfSource,
 };
-use crate::def::{System, ProgResult};
+use crate::def::{Behavior, Generator, System, ProgResult};
 use arrayvec::ArrayVec;
 
+// The instructions of the compiled program. Runs of instructions, linear loops and scan loops
+// are executed at once.
 #[derive(Clone, Copy)]
 enum Instruction {
-    Flip,
-    Inc,
-    Left,
-    Right,
+    // A run of `+`, which flips the cell if its length is odd.
+    Flip(usize),
+    // A run of `.`.
+    Inc(usize),
+    Left(usize),
+    Right(usize),
+    // Precedes the linear loop `loops[index]`, which ends before `end`. Executes the whole loop
+    // when possible, otherwise continues to the loop itself.
+    Linear { index: usize, end: usize },
+    // Precedes the loop `[>]` (or `[<]`), which ends before `end`. Moves the head over the run of
+    // cells with 1 at once.
+    Scan { right: bool, end: usize },
     StartLoop(usize),
     EndLoop(usize),
 }
 
+type Compiled = BfCompiled<Instruction>;
+
 #[derive(Debug)]
 pub struct BfCount {}
 
@@ -22,104 +36,254 @@ impl BfCount {
         BfCount {}
     }
 
-    fn compile(source: &BfSource) -> ArrayVec<Instruction, 28> {
+    fn compile(source: &[BfRawInstruction]) -> Compiled {
         let mut open_loops = ArrayVec::<usize, 28>::new();
-        let mut program = ArrayVec::new();
+        let mut program = Compiled::new();
+        let code = &mut program.code;
 
-        for &instruction in source.0.iter() {
-            let inst = match instruction {
-                BfRawInstruction::Print => Instruction::Inc,
-                BfRawInstruction::Plus => Instruction::Flip,
+        let mut i = 0;
+        while i < source.len() {
+            let run = source[i..].iter().take_while(|&&inst| inst == source[i]).count();
+            match source[i] {
+                BfRawInstruction::Print => {
+                    code.push(Instruction::Inc(run));
+                    i += run;
+                }
+                BfRawInstruction::Plus => {
+                    code.push(Instruction::Flip(run));
+                    i += run;
+                }
                 BfRawInstruction::Minus => unreachable!(),
-                BfRawInstruction::Left => Instruction::Left,
-                BfRawInstruction::Right => Instruction::Right,
+                BfRawInstruction::Left => {
+                    code.push(Instruction::Left(run));
+                    i += run;
+                }
+                BfRawInstruction::Right => {
+                    code.push(Instruction::Right(run));
+                    i += run;
+                }
                 BfRawInstruction::StartLoop => {
-                    open_loops.push(program.len());
-                    Instruction::StartLoop(0)
+                    if let Some(linear) = BfLinearLoop::at(source, i) {
+                        code.push(Instruction::Linear { index: program.loops.len(), end: 0 });
+                        program.loops.push(linear);
+                    } else if let Some(right) = Self::scan_loop(&source[i..]) {
+                        code.push(Instruction::Scan { right, end: 0 });
+                    }
+                    open_loops.push(code.len());
+                    code.push(Instruction::StartLoop(0));
+                    i += 1;
                 }
                 BfRawInstruction::EndLoop => {
                     let open = open_loops.pop().unwrap();
-                    program[open] = Instruction::StartLoop(program.len() + 1);
-                    Instruction::EndLoop(open)
+                    code[open] = Instruction::StartLoop(code.len() + 1);
+                    code.push(Instruction::EndLoop(open));
+                    let end = code.len();
+                    match open.checked_sub(1).map(|before| code[before]) {
+                        Some(Instruction::Linear { index, end: _ }) => {
+                            code[open - 1] = Instruction::Linear { index, end };
+                        }
+                        Some(Instruction::Scan { right, end: _ }) => {
+                            code[open - 1] = Instruction::Scan { right, end };
+                        }
+                        _ => {}
+                    }
+                    i += 1;
                 }
-            };
-            program.push(inst);
+            }
         }
 
         program
     }
 
-    fn maybe_extend_tape(tape: &mut Vec<bool>, pos: usize) {
-        while pos >= tape.len() {
-            tape.push(false);
+    // The direction of the loop `[>]` or `[<]` at the start of the source.
+    fn scan_loop(source: &[BfRawInstruction]) -> Option<bool> {
+        use BfRawInstruction::{EndLoop, Left, Right, StartLoop};
+        match source {
+            [StartLoop, Right, EndLoop, ..] => Some(true),
+            [StartLoop, Left, EndLoop, ..] => Some(false),
+            _ => None,
         }
     }
-}
-
-impl System for BfCount {
-    type Output = u64;
-    type Program = BfSource;
 
-    fn generate(&self, limit: usize) -> BfNaiveGenerator {
-        BfNaiveGenerator::new(limit, false, true)
-    }
+    // Runs the program starting from the instruction `ip` until it ends. Returns an error or
+    // a timeout if the execution is stopped.
+    fn run(
+        program: &Compiled,
+        state: &mut BfCountState,
+        mut ip: usize,
+        max_steps: usize,
+    ) -> Result<(), ProgResult<u64>> {
+        let BfCountState { tape, pos, output, step } = state;
+        let code = &program.code;
+        let mut detector = BfCycleDetector::new();
 
-    fn execute(&self, source: &BfSource, max_steps: usize) -> ProgResult<u64> {
-        let program = Self::compile(source);
-
-        let mut step = 0;
-        let mut tape = Vec::new();
-        let mut pos = 0;
-        let mut output = 0;
-        let mut ip = 0;
-
-        while step < max_steps && ip < program.len() {
-            let inst = program[ip];
-            match inst {
-                Instruction::Flip => {
-                    Self::maybe_extend_tape(&mut tape, pos);
-                    tape[pos] = !tape[pos];
+        if *step >= max_steps {
+            return Err(ProgResult::Timeout);
+        }
+        while ip < code.len() {
+            match code[ip] {
+                Instruction::Flip(count) => {
+                    spend(step, count, max_steps)?;
+                    tape.extend(*pos as isize);
+                    if count % 2 == 1 {
+                        tape.flip(*pos as isize);
+                    }
                     ip += 1;
                 }
-                Instruction::Inc => {
-                    output += 1;
+                Instruction::Inc(count) => {
+                    spend(step, count, max_steps)?;
+                    *output += count as u64;
                     ip += 1;
                 }
-                Instruction::Left => {
-                    if pos == 0 {
-                        return ProgResult::Error;
+                Instruction::Left(count) => {
+                    if *pos < count {
+                        spend(step, *pos, max_steps)?;
+                        return Err(ProgResult::Error);
                     }
-                    pos -= 1;
+                    spend(step, count, max_steps)?;
+                    *pos -= count;
+                    detector.access(*pos);
                     ip += 1;
                 }
-                Instruction::Right => {
-                    pos += 1;
+                Instruction::Right(count) => {
+                    spend(step, count, max_steps)?;
+                    *pos += count;
                     ip += 1;
                 }
+                Instruction::Linear { index, end } => {
+                    let linear = &program.loops[index];
+                    tape.extend(*pos as isize);
+                    // A loop that isn't entered, or that leaves the tape, is executed as usual.
+                    if !tape.get(*pos as isize) || (*pos as isize) + linear.min_offset < 0 {
+                        ip += 1;
+                        continue;
+                    }
+                    // The loop ends after one iteration if it flips the tested cell, and its end
+                    // can't be reached otherwise.
+                    if linear.tested_change() % 2 == 0 {
+                        return Err(ProgResult::NonHalting(Behavior::Unreachable));
+                    }
+                    spend(step, linear.steps(1), max_steps)?;
+                    tape.extend(*pos as isize + linear.max_offset);
+                    detector.access((*pos as isize + linear.min_offset) as usize);
+                    for &(offset, change) in linear.changes.iter() {
+                        if change % 2 != 0 {
+                            tape.flip(*pos as isize + offset);
+                        }
+                    }
+                    *output += linear.prints as u64;
+                    ip = end;
+                }
+                Instruction::Scan { right, end } => {
+                    // The loop stops at the first blank cell. A loop that leaves the tape is
+                    // executed as usual.
+                    let stop = tape.skip_ones(*pos as isize, right);
+                    if stop < 0 {
+                        ip += 1;
+                        continue;
+                    }
+                    // Each iteration tests the cell, moves the head and jumps back.
+                    let iterations = stop.abs_diff(*pos as isize);
+                    spend(step, 3 * iterations + 1, max_steps)?;
+                    *pos = stop as usize;
+                    tape.extend(stop);
+                    detector.access(*pos);
+                    ip = end;
+                }
                 Instruction::StartLoop(target) => {
-                    Self::maybe_extend_tape(&mut tape, pos);
-                    if tape[pos] {
+                    spend(step, 1, max_steps)?;
+                    tape.extend(*pos as isize);
+                    if let Some(behavior) = detector.test(ip, *pos, tape) {
+                        return Err(ProgResult::NonHalting(behavior));
+                    }
+                    if tape.get(*pos as isize) {
                         ip += 1
                     } else {
                         ip = target
                     }
                 }
                 Instruction::EndLoop(target) => {
+                    spend(step, 1, max_steps)?;
                     ip = target;
                 }
             }
-            step += 1;
         }
 
-        if step >= max_steps {
-            ProgResult::Timeout
-        } else {
-            ProgResult::Out {
-                output,
-                steps: step,
-            }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct BfCountState {
+    tape: BitTape,
+    pos: usize,
+    output: u64,
+    step: usize,
+}
+
+impl BfCountState {
+    fn new() -> Self {
+        BfCountState {
+            tape: BitTape::new(),
+            pos: 0,
+            output: 0,
+            step: 0,
         }
     }
+}
+
+impl BfIncremental for BfCount {
+    type State = BfCountState;
+
+    fn ranking(&self) -> BfRanking {
+        BfRanking::new(false, true)
+    }
+
+    fn initial_state(&self) -> BfCountState {
+        BfCountState::new()
+    }
+
+    fn step(
+        &self,
+        state: &mut BfCountState,
+        inst: BfRawInstruction,
+        max_steps: usize,
+    ) -> Option<ProgResult<u64>> {
+        Self::run(&Self::compile(&[inst]), state, 0, max_steps).err()
+    }
+
+    fn resume(
+        &self,
+        source: &BfSource,
+        mut state: BfCountState,
+        ip: usize,
+        max_steps: usize,
+    ) -> ProgResult<u64> {
+        if source.never_halts(true) {
+            return ProgResult::NonHalting(Behavior::Unreachable);
+        }
+        let program = Self::compile(&source.0[ip..]);
+        match Self::run(&program, &mut state, 0, max_steps) {
+            Err(result) => result,
+            Ok(()) => ProgResult::Out {
+                output: state.output,
+                steps: state.step,
+            },
+        }
+    }
+}
+
+impl System for BfCount {
+    type Output = u64;
+    type Program = BfSource;
+
+    fn generate(&self, limit: usize) -> impl Generator<BfSource> {
+        BfCanonicalGenerator::new(limit, false, true, true)
+    }
+
+    fn execute(&self, source: &BfSource, max_steps: usize) -> ProgResult<u64> {
+        self.resume(source, BfCountState::new(), 0, max_steps)
+    }
 
     fn valid_output(output: &u64) -> bool {
         *output > 0
@@ -129,8 +293,15 @@ impl System for BfCount {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::brainfuck::{self, BfNaiveGenerator, BfPrefixSearch};
     use crate::stat::Stat;
-    use crate::def::Generator;
+
+// The reference interpreter on a binary tape.
+fn reference_execute(source: &BfSource, max_steps: usize) -> ProgResult<u64> {
+    brainfuck::reference_execute(source, max_steps, |_: &[bool], prints, steps| {
+        ProgResult::Out { output: prints, steps }
+    })
+}
 
 #[test]
 fn results_match() {
@@ -157,4 +328,96 @@ fn results_match() {
     }
 }
 
-}
\ No newline at end of file
+#[test]
+fn sizes_match() {
+    // The canonical programs smaller than the range stand for programs in it, and those in the
+    // range stand for larger programs too, which are not counted.
+    let comp = BfCount::new();
+    let mut gen1 = comp.generate(8).sizes(3..=6);
+    let mut stat1: Stat<BfCount> = Stat::new();
+    let mut total1 = 0;
+
+    while let Some((program, weight)) = gen1.next() {
+        let result = comp.execute(&program, 1000);
+        stat1.register(&program, &result, weight);
+        total1 += weight;
+    }
+
+    let mut gen2 = BfNaiveGenerator::new(8, false, true).sizes(3..=6);
+    let mut stat2: Stat<BfCount> = Stat::new();
+    let mut total2 = 0;
+
+    while let Some((program, weight)) = gen2.next() {
+        let result = comp.execute(&program, 1000);
+        stat2.register(&program, &result, weight);
+        total2 += weight;
+    }
+
+    assert!(stat1.matches_outputs(&stat2));
+    assert_eq!(total1, total2);
+}
+
+#[test]
+fn prefix_search_matches() {
+    let comp = BfCount::new();
+
+    for max_size in 1..9 {
+        let mut search = BfPrefixSearch::new(&comp, max_size, 1000);
+        let mut stat1: Stat<BfCount> = Stat::new();
+
+        while let Some((program, result, weight)) = search.next() {
+            stat1.register(&program, &result, weight);
+        }
+
+        let mut gen2 = BfNaiveGenerator::new(max_size, false, true);
+        let mut stat2: Stat<BfCount> = Stat::new();
+
+        while let Some((program, weight)) = gen2.next() {
+            let result = comp.execute(&program, 1000);
+            stat2.register(&program, &result, weight);
+        }
+
+        assert!(stat1.matches_outputs(&stat2));
+        assert!(stat1.matches_failures(&stat2));
+    }
+}
+
+#[test]
+fn detects_non_halting() {
+    let comp = BfCount::new();
+    let result = |source: &str| comp.execute(&source.parse().unwrap(), 100_000);
+
+    // An empty loop, a cycle of two iterations, and loops moving right forever.
+    for source in ["+[]", "+[>+<.]", "+[>+]", "+>+[<[>>+<<+]>]"] {
+        assert!(matches!(result(source), ProgResult::NonHalting(_)), "{}", source);
+    }
+    assert!(matches!(result("+[>+<+]"), ProgResult::Out { output: 0, steps: 8 }));
+}
+
+#[test]
+fn optimized_matches_reference() {
+    let comp = BfCount::new();
+    let mut gen = BfNaiveGenerator::new(7, false, true);
+
+    while let Some((program, _)) = gen.next() {
+        for max_steps in [0, 3, 10, 100] {
+            let result = comp.execute(&program, max_steps);
+            let expected = reference_execute(&program, max_steps);
+            if let ProgResult::NonHalting(_) = result {
+                let long = reference_execute(&program, 10_000);
+                assert!(matches!(long, ProgResult::Timeout), "{}", program);
+            } else {
+                assert_eq!(format!("{:?}", result), format!("{:?}", expected), "{}", program);
+            }
+        }
+    }
+
+    // Scan loops over runs of cells to the right and to the left, and off the left end.
+    for source in ["+>+>+<<[>].", "+>>+>+[<].", "+>+>+[<]."] {
+        let program = source.parse().unwrap();
+        let result = comp.execute(&program, 1000);
+        let expected = reference_execute(&program, 1000);
+        assert_eq!(format!("{:?}", result), format!("{:?}", expected), "{}", source);
+    }
+}
+}
@@ -1,19 +1,27 @@
 use crate::brainfuck::{
-    BfNaiveGenerator, BfRawInstruction, BfSource,
+    spend, BfCanonicalGenerator, BfCompiled, BfCycleDetector, BfIncremental, BfLinearLoop,
+    BfRanking, BfRawInstruction, BfSource,
 };
-use crate::def::{System, ProgResult};
+use crate::def::{Behavior, Generator, System, ProgResult};
 use arrayvec::ArrayVec;
 
+// The instructions of the compiled program. Runs of instructions and linear loops are executed
+// at once.
 #[derive(Clone, Copy)]
 enum Instruction {
-    Plus,
-    Minus,
-    Left,
-    Right,
+    // A run of `+` and `-` with the total change of the cell.
+    Add { delta: i32, len: usize },
+    Left(usize),
+    Right(usize),
+    // Precedes the linear loop `loops[index]`, which ends before `end`. Executes the whole loop
+    // when possible, otherwise continues to the loop itself.
+    Linear { index: usize, end: usize },
     StartLoop(usize),
     EndLoop(usize),
 }
 
+type Compiled = BfCompiled<Instruction>;
+
 #[derive(Debug)]
 pub struct BfNum0 {
 }
@@ -23,28 +31,60 @@ impl BfNum0 {
         BfNum0 {}
     }
 
-    fn compile(source: &BfSource) -> ArrayVec<Instruction, 28> {
+    fn compile(source: &[BfRawInstruction]) -> Compiled {
         let mut open_loops = ArrayVec::<usize, 28>::new();
-        let mut program = ArrayVec::new();
+        let mut program = Compiled::new();
+        let code = &mut program.code;
 
-        for &instruction in source.0.iter() {
-            let inst = match instruction {
-                BfRawInstruction::Plus => Instruction::Plus,
-                BfRawInstruction::Minus => Instruction::Minus,
+        let mut i = 0;
+        while i < source.len() {
+            let run = source[i..].iter().take_while(|&&inst| inst == source[i]).count();
+            match source[i] {
+                BfRawInstruction::Plus | BfRawInstruction::Minus => {
+                    let len = source[i..]
+                        .iter()
+                        .take_while(|&&inst| {
+                            inst == BfRawInstruction::Plus || inst == BfRawInstruction::Minus
+                        })
+                        .count();
+                    let delta = source[i..i + len]
+                        .iter()
+                        .map(|&inst| if inst == BfRawInstruction::Plus { 1 } else { -1 })
+                        .sum();
+                    code.push(Instruction::Add { delta, len });
+                    i += len;
+                }
                 BfRawInstruction::Print => unreachable!(),
-                BfRawInstruction::Left => Instruction::Left,
-                BfRawInstruction::Right => Instruction::Right,
+                BfRawInstruction::Left => {
+                    code.push(Instruction::Left(run));
+                    i += run;
+                }
+                BfRawInstruction::Right => {
+                    code.push(Instruction::Right(run));
+                    i += run;
+                }
                 BfRawInstruction::StartLoop => {
-                    open_loops.push(program.len());
-                    Instruction::StartLoop(0)
+                    if let Some(linear) = BfLinearLoop::at(source, i) {
+                        code.push(Instruction::Linear { index: program.loops.len(), end: 0 });
+                        program.loops.push(linear);
+                    }
+                    open_loops.push(code.len());
+                    code.push(Instruction::StartLoop(0));
+                    i += 1;
                 }
                 BfRawInstruction::EndLoop => {
                     let open = open_loops.pop().unwrap();
-                    program[open] = Instruction::StartLoop(program.len() + 1);
-                    Instruction::EndLoop(open)
+                    code[open] = Instruction::StartLoop(code.len() + 1);
+                    code.push(Instruction::EndLoop(open));
+                    if let Some(Instruction::Linear { index, end: _ }) = open
+                        .checked_sub(1)
+                        .map(|linear| code[linear])
+                    {
+                        code[open - 1] = Instruction::Linear { index, end: code.len() };
+                    }
+                    i += 1;
                 }
-            };
-            program.push(inst);
+            }
         }
 
         program
@@ -55,71 +95,169 @@ impl BfNum0 {
             tape.push(0);
         }
     }
-}
-
-impl System for BfNum0 {
-    type Output = i64;
-    type Program = BfSource;
 
-    fn valid_output(o: &i64) -> bool {
-        *o > 0
+    // The number of iterations of a linear loop, or None if it never ends. The cells wrap
+    // around, as `+` and `-` do in release builds.
+    fn iterations(value: i32, change: i32) -> Option<u64> {
+        match change {
+            -1 => Some(value as u32 as u64),
+            1 => Some((value as u32).wrapping_neg() as u64),
+            _ => None,
+        }
     }
 
-    fn generate(&self, limit: usize) -> BfNaiveGenerator {
-        BfNaiveGenerator::new(limit, true, false)
-    }
+    // Runs the program starting from the instruction `ip` until it ends. Returns an error or
+    // a timeout if the execution is stopped.
+    fn run(
+        program: &Compiled,
+        state: &mut BfNum0State,
+        mut ip: usize,
+        max_steps: usize,
+    ) -> Result<(), ProgResult<i64>> {
+        let BfNum0State { tape, pos, step } = state;
+        let code = &program.code;
+        let mut detector = BfCycleDetector::new();
 
-    fn execute(&self, source: &BfSource, max_steps: usize) -> ProgResult<i64> {
-        let program = Self::compile(source);
-
-        let mut step = 0;
-        let mut tape = vec![0];
-        let mut pos = 0;
-        let mut ip = 0;
-
-        while step < max_steps && ip < program.len() {
-            let inst = program[ip];
-            match inst {
-                Instruction::Plus => {
-                    Self::maybe_extend_tape(&mut tape, pos);
-                    tape[pos] += 1;
-                    ip += 1;
-                }
-                Instruction::Minus => {
-                    Self::maybe_extend_tape(&mut tape, pos);
-                    tape[pos] -= 1;
+        if *step >= max_steps {
+            return Err(ProgResult::Timeout);
+        }
+        while ip < code.len() {
+            match code[ip] {
+                Instruction::Add { delta, len } => {
+                    spend(step, len, max_steps)?;
+                    Self::maybe_extend_tape(tape, *pos);
+                    tape[*pos] += delta;
                     ip += 1;
                 }
-                Instruction::Left => {
-                    if pos == 0 {
-                        return ProgResult::Error;
+                Instruction::Left(count) => {
+                    if *pos < count {
+                        spend(step, *pos, max_steps)?;
+                        return Err(ProgResult::Error);
                     }
-                    pos -= 1;
+                    spend(step, count, max_steps)?;
+                    *pos -= count;
+                    detector.access(*pos);
                     ip += 1;
                 }
-                Instruction::Right => {
-                    pos += 1;
+                Instruction::Right(count) => {
+                    spend(step, count, max_steps)?;
+                    *pos += count;
                     ip += 1;
                 }
+                Instruction::Linear { index, end } => {
+                    let linear = &program.loops[index];
+                    Self::maybe_extend_tape(tape, *pos);
+                    let value = tape[*pos];
+                    // A loop that isn't entered, or that leaves the tape, is executed as usual.
+                    let change = linear.tested_change();
+                    if value == 0 || (*pos as isize) + linear.min_offset < 0 || change.abs() > 1 {
+                        ip += 1;
+                        continue;
+                    }
+                    let Some(iterations) = Self::iterations(value, change) else {
+                        return Err(ProgResult::NonHalting(Behavior::Unreachable));
+                    };
+                    spend(step, linear.steps(iterations), max_steps)?;
+                    Self::maybe_extend_tape(tape, (*pos as isize + linear.max_offset) as usize);
+                    detector.access((*pos as isize + linear.min_offset) as usize);
+                    for &(offset, change) in linear.changes.iter() {
+                        let cell = &mut tape[(*pos as isize + offset) as usize];
+                        *cell = cell.wrapping_add((iterations as i32).wrapping_mul(change));
+                    }
+                    ip = end;
+                }
                 Instruction::StartLoop(target) => {
-                    Self::maybe_extend_tape(&mut tape, pos);
-                    if tape[pos] != 0 {
+                    spend(step, 1, max_steps)?;
+                    Self::maybe_extend_tape(tape, *pos);
+                    if let Some(behavior) = detector.test(ip, *pos, tape) {
+                        return Err(ProgResult::NonHalting(behavior));
+                    }
+                    if tape[*pos] != 0 {
                         ip += 1
                     } else {
                         ip = target
                     }
                 }
                 Instruction::EndLoop(target) => {
+                    spend(step, 1, max_steps)?;
                     ip = target;
                 }
             }
-            step += 1;
         }
 
-        if step >= max_steps {
-            ProgResult::Timeout
-        } else if tape[0] > 0 {
-            ProgResult::Out { output: tape[0] as i64, steps: step }
+        Ok(())
+    }
+}
+
+impl System for BfNum0 {
+    type Output = i64;
+    type Program = BfSource;
+
+    fn valid_output(o: &i64) -> bool {
+        *o > 0
+    }
+
+    fn generate(&self, limit: usize) -> impl Generator<BfSource> {
+        BfCanonicalGenerator::new(limit, true, false, false)
+    }
+
+    fn execute(&self, source: &BfSource, max_steps: usize) -> ProgResult<i64> {
+        self.resume(source, BfNum0State::new(), 0, max_steps)
+    }
+}
+
+#[derive(Clone)]
+pub struct BfNum0State {
+    tape: Vec<i32>,
+    pos: usize,
+    step: usize,
+}
+
+impl BfNum0State {
+    fn new() -> Self {
+        BfNum0State {
+            tape: vec![0],
+            pos: 0,
+            step: 0,
+        }
+    }
+}
+
+impl BfIncremental for BfNum0 {
+    type State = BfNum0State;
+
+    fn ranking(&self) -> BfRanking {
+        BfRanking::new(true, false)
+    }
+
+    fn initial_state(&self) -> BfNum0State {
+        BfNum0State::new()
+    }
+
+    fn step(
+        &self,
+        state: &mut BfNum0State,
+        inst: BfRawInstruction,
+        max_steps: usize,
+    ) -> Option<ProgResult<i64>> {
+        Self::run(&Self::compile(&[inst]), state, 0, max_steps).err()
+    }
+
+    fn resume(
+        &self,
+        source: &BfSource,
+        mut state: BfNum0State,
+        ip: usize,
+        max_steps: usize,
+    ) -> ProgResult<i64> {
+        if source.never_halts(false) {
+            return ProgResult::NonHalting(Behavior::Unreachable);
+        }
+        let program = Self::compile(&source.0[ip..]);
+        if let Err(result) = Self::run(&program, &mut state, 0, max_steps) {
+            result
+        } else if state.tape[0] > 0 {
+            ProgResult::Out { output: state.tape[0] as i64, steps: state.step }
         } else {
             ProgResult::Error
         }
@@ -129,8 +267,19 @@ impl System for BfNum0 {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::brainfuck::{self, BfNaiveGenerator, BfPrefixSearch};
     use crate::stat::Stat;
-    use crate::def::Generator;
+
+// The reference interpreter on a tape of integers, with the output in the first cell.
+fn reference_execute(source: &BfSource, max_steps: usize) -> ProgResult<i64> {
+    brainfuck::reference_execute(source, max_steps, |tape: &[i32], _, steps| {
+        if tape[0] > 0 {
+            ProgResult::Out { output: tape[0] as i64, steps }
+        } else {
+            ProgResult::Error
+        }
+    })
+}
 
 #[test]
 fn results_match() {
@@ -157,4 +306,61 @@ fn results_match() {
     }
 }
 
-}
\ No newline at end of file
+#[test]
+fn prefix_search_matches() {
+    let comp = BfNum0::new();
+
+    for max_size in 1..9 {
+        let mut search = BfPrefixSearch::new(&comp, max_size, 1000);
+        let mut stat1: Stat<BfNum0> = Stat::new();
+
+        while let Some((program, result, weight)) = search.next() {
+            stat1.register(&program, &result, weight);
+        }
+
+        let mut gen2 = BfNaiveGenerator::new(max_size, true, false);
+        let mut stat2: Stat<BfNum0> = Stat::new();
+
+        while let Some((program, weight)) = gen2.next() {
+            let result = comp.execute(&program, 1000);
+            stat2.register(&program, &result, weight);
+        }
+
+        assert!(stat1.matches_outputs(&stat2));
+        assert!(stat1.matches_failures(&stat2));
+    }
+}
+
+#[test]
+fn detects_non_halting() {
+    let comp = BfNum0::new();
+    let result = |source: &str| comp.execute(&source.parse().unwrap(), 100_000);
+
+    // A loop that doesn't change the tested cell, and loops moving right forever, also with
+    // a cell written ahead of the head.
+    for source in ["+[>+<]", "+[>+]", "+[>+>+<]", "+[[-]>+]"] {
+        assert!(matches!(result(source), ProgResult::NonHalting(_)), "{}", source);
+    }
+    // A counter that grows forever is not detected.
+    assert!(matches!(result("+[>+<[-]+]"), ProgResult::Timeout));
+}
+
+#[test]
+fn optimized_matches_reference() {
+    let comp = BfNum0::new();
+    let mut gen = BfNaiveGenerator::new(7, true, false);
+
+    while let Some((program, _)) = gen.next() {
+        for max_steps in [0, 3, 10, 100] {
+            let result = comp.execute(&program, max_steps);
+            let expected = reference_execute(&program, max_steps);
+            if let ProgResult::NonHalting(_) = result {
+                let long = reference_execute(&program, 10_000);
+                assert!(matches!(long, ProgResult::Timeout), "{}", program);
+            } else {
+                assert_eq!(format!("{:?}", result), format!("{:?}", expected), "{}", program);
+            }
+        }
+    }
+}
+}
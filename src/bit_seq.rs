@@ -0,0 +1,276 @@
+use crate::def::{Generator, Ranking, Sized};
+use std::cmp::Ordering;
+
+// A finite sequence of bits, used as the output of systems that generate bit strings.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BitSeq(pub Vec<bool>);
+
+impl BitSeq {
+    pub fn new() -> Self {
+        BitSeq(Vec::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, bit: bool) {
+        self.0.push(bit);
+    }
+
+    // Appends the lowest `nbits` bits of n, most significant bit first, as read by `BitReader`.
+    pub fn push_bits(&mut self, n: usize, nbits: usize) {
+        for i in (0..nbits).rev() {
+            self.push((n >> i) & 1 != 0);
+        }
+    }
+
+    // Appends the Elias gamma code of n >= 1.
+    pub fn push_gamma(&mut self, n: usize) {
+        let zeros = (usize::BITS - n.leading_zeros() - 1) as usize;
+        self.push_bits(0, zeros);
+        self.push_bits(n, zeros + 1);
+    }
+}
+
+// Shorter sequences go first, sequences of the same length are ordered lexicographically.
+impl Ord for BitSeq {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.len().cmp(&other.0.len()).then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for BitSeq {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for BitSeq {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "ε");
+        }
+        for &bit in self.0.iter() {
+            write!(f, "{}", bit as usize)?;
+        }
+        std::fmt::Result::Ok(())
+    }
+}
+
+// Reads the lowest `remaining` bits of `code`, most significant bit first.
+pub struct BitReader {
+    code: usize,
+    remaining: usize,
+}
+
+impl BitReader {
+    pub fn new(code: usize, len: usize) -> Self {
+        BitReader { code, remaining: len }
+    }
+
+    pub fn from_bits(bits: &BitSeq) -> Self {
+        let code = bits.0.iter().fold(0, |code, &bit| (code << 1) | bit as usize);
+        BitReader::new(code, bits.len())
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    pub fn read(&mut self) -> Option<bool> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some((self.code >> self.remaining) & 1 != 0)
+    }
+
+    pub fn read_bits(&mut self, nbits: usize) -> Option<usize> {
+        let mut n = 0;
+        for _ in 0..nbits {
+            n = (n << 1) | self.read()? as usize;
+        }
+        Some(n)
+    }
+
+    // Elias gamma code of n >= 1.
+    pub fn read_gamma(&mut self) -> Option<usize> {
+        let mut zeros = 0;
+        while !self.read()? {
+            zeros += 1;
+        }
+        Some((1 << zeros) | self.read_bits(zeros)?)
+    }
+}
+
+// Enumerates the programs of a system with a bit code, by trying all bit strings in the order of
+// length. `parse` should consume the whole string and return None if it is not a valid code.
+pub struct BitCodeGenerator<P> {
+    parse: fn(&mut BitReader) -> Option<P>,
+    max_bits: usize,
+    len: usize,
+    idx: usize,
+    total_for_len: usize,
+}
+
+impl<P> BitCodeGenerator<P> {
+    pub fn new(max_bits: usize, parse: fn(&mut BitReader) -> Option<P>) -> Self {
+        BitCodeGenerator {
+            parse,
+            max_bits,
+            len: 0,
+            idx: 0,
+            total_for_len: 0,
+        }
+    }
+}
+
+impl<P> Generator<P> for BitCodeGenerator<P> {
+    fn next(&mut self) -> Option<(P, usize)> {
+        loop {
+            if self.idx >= self.total_for_len {
+                self.len += 1;
+                self.idx = 0;
+                self.total_for_len = 1 << self.len;
+            }
+
+            if self.len > self.max_bits {
+                return None;
+            }
+
+            let mut reader = BitReader::new(self.idx, self.len);
+            self.idx += 1;
+
+            if let Some(program) = (self.parse)(&mut reader) {
+                if reader.remaining() == 0 {
+                    return Some((program, 1));
+                }
+            }
+        }
+    }
+}
+
+// Ranks the programs of a bit code in the order of `BitCodeGenerator`, which is the numeric order
+// of the codes of the same length. `completions(prefix, len)` is the number of valid codes of
+// `len` bits that start with `prefix`, and `encode` is the inverse of `parse`.
+#[derive(Clone)]
+pub struct BitCodeRanking<P> {
+    parse: fn(&mut BitReader) -> Option<P>,
+    encode: fn(&P) -> BitSeq,
+    completions: fn(&[bool], usize) -> u128,
+    // The number of valid codes by length.
+    counts: Vec<u128>,
+}
+
+impl<P> BitCodeRanking<P> {
+    pub fn new(
+        parse: fn(&mut BitReader) -> Option<P>,
+        encode: fn(&P) -> BitSeq,
+        completions: fn(&[bool], usize) -> u128,
+    ) -> Self {
+        // The codes are read from a usize.
+        let counts = (0..usize::BITS as usize).map(|len| completions(&[], len)).collect();
+        BitCodeRanking {
+            parse,
+            encode,
+            completions,
+            counts,
+        }
+    }
+}
+
+impl<P: Sized> Ranking<P> for BitCodeRanking<P> {
+    fn count(&self, size: usize) -> u128 {
+        self.counts[size]
+    }
+
+    // Counts the valid codes that are smaller in the first bit where they differ.
+    fn rank(&self, program: &P) -> u128 {
+        let code = (self.encode)(program);
+        let mut index = 0;
+        for (i, &bit) in code.0.iter().enumerate() {
+            if bit {
+                let mut prefix = code.0[..i].to_vec();
+                prefix.push(false);
+                index += (self.completions)(&prefix, code.len());
+            }
+        }
+        index
+    }
+
+    fn unrank(&self, size: usize, mut index: u128) -> P {
+        assert!(index < self.count(size));
+        let mut code = BitSeq::new();
+        for _ in 0..size {
+            code.push(false);
+            let count = (self.completions)(&code.0, size);
+            if index >= count {
+                index -= count;
+                *code.0.last_mut().unwrap() = true;
+            }
+        }
+        (self.parse)(&mut BitReader::from_bits(&code)).unwrap()
+    }
+
+    fn max_size(&self) -> usize {
+        self.counts.len() - 1
+    }
+
+    fn check(&self, program: &P) -> Result<(), String> {
+        if program.size() <= self.max_size() {
+            Ok(())
+        } else {
+            Err(format!("The programs have at most size {}", self.max_size()))
+        }
+    }
+}
+
+// The numbers of the concatenations of two parts by length, from their numbers by length, up to
+// the length of `a`.
+pub fn convolve(a: &[u128], b: &[u128]) -> Vec<u128> {
+    (0..a.len())
+        .map(|n| (0..=n.min(b.len().saturating_sub(1))).map(|k| a[n - k] * b[k]).sum())
+        .collect()
+}
+
+// The number of ways to end an Elias gamma code by length, after reading `zeros` zeros of its
+// first part, for the lengths up to `max_len`. With no zeros read, these are the whole codes.
+pub fn gamma_ends(zeros: usize, max_len: usize) -> Vec<u128> {
+    let mut ends = vec![0; max_len + 1];
+    for total in zeros.. {
+        let len = total - zeros + 1 + total;
+        if len > max_len {
+            break;
+        }
+        ends[len] = 1 << total;
+    }
+    ends
+}
+
+// The ways to end an Elias gamma code that starts at `prefix[*i]` by length, up to `max_len`, or
+// None if the code ends within the prefix, in which case it is skipped.
+pub fn gamma_end(prefix: &[bool], i: &mut usize, max_len: usize) -> Option<Vec<u128>> {
+    let mut zeros = 0;
+    while *i < prefix.len() && !prefix[*i] {
+        zeros += 1;
+        *i += 1;
+    }
+    if *i == prefix.len() {
+        return Some(gamma_ends(zeros, max_len));
+    }
+    *i += 1 + zeros;
+    if *i <= prefix.len() {
+        return None;
+    }
+    let mut ends = vec![0; max_len + 1];
+    let missing = *i - prefix.len();
+    if missing <= max_len {
+        ends[missing] = 1 << missing;
+    }
+    Some(ends)
+}
@@ -0,0 +1,185 @@
+// A binary tape infinite in both directions, packed into 64-bit words. The accessed cells are
+// `start()..end()`, and all the cells beyond them are blank. Two tapes are equal if they have
+// the same accessed cells.
+#[derive(Clone, Debug, Default)]
+pub struct BitTape {
+    // The words of the cells from the position `origin`, a multiple of 64, which cover all the
+    // accessed cells.
+    words: Vec<u64>,
+    origin: isize,
+    start: isize,
+    end: isize,
+}
+
+impl BitTape {
+    pub fn new() -> Self {
+        BitTape::default()
+    }
+
+    pub fn start(&self) -> isize {
+        self.start
+    }
+
+    pub fn end(&self) -> isize {
+        self.end
+    }
+
+    // Marks the cell as accessed.
+    pub fn extend(&mut self, pos: isize) {
+        if self.start <= pos && pos < self.end {
+            return;
+        }
+        if self.start == self.end {
+            (self.start, self.end) = (pos, pos + 1);
+        } else {
+            self.start = self.start.min(pos);
+            self.end = self.end.max(pos + 1);
+        }
+        let index = (pos - self.origin).div_euclid(64);
+        if index < 0 {
+            // The tape grows to the left by at least its length, as it does to the right.
+            let words = (-index as usize).max(self.words.len());
+            self.words.splice(0..0, std::iter::repeat_n(0, words));
+            self.origin -= 64 * words as isize;
+        } else if index as usize >= self.words.len() {
+            self.words.resize(index as usize + 1, 0);
+        }
+    }
+
+    pub fn get(&self, pos: isize) -> bool {
+        let i = pos.wrapping_sub(self.origin) as usize;
+        self.words.get(i / 64).is_some_and(|word| word >> (i % 64) & 1 == 1)
+    }
+
+    // Reads the cell, and marks it as accessed.
+    pub fn read(&mut self, pos: isize) -> bool {
+        self.extend(pos);
+        let i = (pos - self.origin) as usize;
+        self.words[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    // Writes a cell that has been accessed.
+    pub fn set(&mut self, pos: isize, value: bool) {
+        let i = (pos - self.origin) as usize;
+        let word = &mut self.words[i / 64];
+        *word = *word & !(1 << (i % 64)) | (value as u64) << (i % 64);
+    }
+
+    // Flips a cell that has been accessed.
+    pub fn flip(&mut self, pos: isize) {
+        let i = (pos - self.origin) as usize;
+        self.words[i / 64] ^= 1 << (i % 64);
+    }
+
+    // The 64 cells from the position, the first one in the lowest bit.
+    fn word(&self, pos: isize) -> u64 {
+        let i = pos - self.origin;
+        let (index, shift) = (i.div_euclid(64), i.rem_euclid(64));
+        let word = |index: isize| {
+            usize::try_from(index).ok().and_then(|index| self.words.get(index)).map_or(0, |w| *w)
+        };
+        if shift == 0 {
+            word(index)
+        } else {
+            word(index) >> shift | word(index + 1) << (64 - shift)
+        }
+    }
+
+    // The `len` cells from the position, at most 64, the first one in the lowest bit.
+    pub fn cells(&self, pos: isize, len: usize) -> u64 {
+        let mask = if len >= 64 { !0 } else { (1 << len) - 1 };
+        self.word(pos) & mask
+    }
+
+    // Whether the `len` cells from `pos` are the same as the cells of the other tape from
+    // `other_pos`. Compares 64 cells at a time.
+    pub fn same_cells(&self, pos: isize, other: &BitTape, other_pos: isize, len: usize) -> bool {
+        (0..len).step_by(64).all(|i| {
+            let mask = if len - i >= 64 { !0 } else { (1 << (len - i)) - 1 };
+            (self.word(pos + i as isize) ^ other.word(other_pos + i as isize)) & mask == 0
+        })
+    }
+
+    // The first blank cell from the position towards the right (or the left). Skips up to 64
+    // cells at a time.
+    pub fn skip_ones(&self, mut pos: isize, right: bool) -> isize {
+        loop {
+            let i = pos.wrapping_sub(self.origin) as usize;
+            let Some(&word) = self.words.get(i / 64) else {
+                return pos;
+            };
+            let bit = i % 64;
+            // The number of cells with 1 from the position to the end of the word.
+            let (ones, remaining) = if right {
+                ((!word >> bit).trailing_zeros() as usize, 64 - bit)
+            } else {
+                ((!word << (63 - bit)).leading_zeros() as usize, bit + 1)
+            };
+            let ones = ones.min(remaining);
+            pos += if right { ones as isize } else { -(ones as isize) };
+            if ones < remaining {
+                return pos;
+            }
+        }
+    }
+}
+
+impl PartialEq for BitTape {
+    fn eq(&self, other: &Self) -> bool {
+        let len = (self.end - self.start) as usize;
+        (self.start, self.end) == (other.start, other.end)
+            && self.same_cells(self.start, other, other.start, len)
+    }
+}
+
+impl Eq for BitTape {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cells() {
+        let mut tape = BitTape::new();
+        let mut cells = vec![false; 400];
+        // The positions from -200 to 199, in a scrambled order.
+        for i in 0..400 {
+            let pos = (i * 37 % 400) as isize - 200;
+            tape.extend(pos);
+            tape.set(pos, i % 3 != 0);
+            cells[(pos + 200) as usize] = i % 3 != 0;
+        }
+        tape.flip(5);
+        cells[205] = !cells[205];
+        assert_eq!((tape.start(), tape.end()), (-200, 200));
+        for pos in -264..264 {
+            let expected = (-200..200).contains(&pos) && cells[(pos + 200) as usize];
+            assert_eq!(tape.get(pos), expected, "{}", pos);
+        }
+
+        for pos in -200..200 {
+            for right in [false, true] {
+                let mut expected = pos;
+                while tape.get(expected) {
+                    expected += if right { 1 } else { -1 };
+                }
+                assert_eq!(tape.skip_ones(pos, right), expected, "{} {}", pos, right);
+            }
+        }
+
+        let mut shifted = BitTape::new();
+        for pos in 0..200 {
+            shifted.extend(pos + 70);
+            shifted.set(pos + 70, tape.get(pos));
+        }
+        assert!(tape.same_cells(0, &shifted, 70, 200));
+        for pos in [-264, -205, -3, 60, 190] {
+            let expected = (0..10).fold(0, |cells, i| cells | (tape.get(pos + i) as u64) << i);
+            assert_eq!(tape.cells(pos, 10), expected, "{}", pos);
+        }
+        assert!(tape.same_cells(-20, &shifted, 50, 220) == (-20..0).all(|x| !tape.get(x)));
+        shifted.flip(269);
+        assert!(tape.same_cells(0, &shifted, 70, 199));
+        assert!(!tape.same_cells(0, &shifted, 70, 200));
+    }
+}
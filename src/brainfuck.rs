@@ -1,10 +1,14 @@
-use crate::def::{Generator, Sized};
+use crate::bit_tape::BitTape;
+use crate::def::{Behavior, Generator, ProgResult, Ranking, Sized, System};
 use arrayvec::ArrayVec;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
 
 // So that ArrayString of this size fit in 32 bytes.
 const BF_MAX_LEN: usize = 28;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub
 enum BfRawInstruction {
     Plus,
@@ -16,7 +20,7 @@ enum BfRawInstruction {
     EndLoop,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct BfSource(pub ArrayVec<BfRawInstruction, BF_MAX_LEN>);
 
 impl std::fmt::Display for BfSource {
@@ -36,90 +40,1221 @@ impl std::fmt::Display for BfSource {
     }
 }
 
+impl std::str::FromStr for BfSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut program = BfSource(ArrayVec::new());
+        for c in s.chars() {
+            let inst = match c {
+                '.' => BfRawInstruction::Print,
+                '+' => BfRawInstruction::Plus,
+                '-' => BfRawInstruction::Minus,
+                '<' => BfRawInstruction::Left,
+                '>' => BfRawInstruction::Right,
+                '[' => BfRawInstruction::StartLoop,
+                ']' => BfRawInstruction::EndLoop,
+                _ => return Err(format!("Unexpected character: {}", c)),
+            };
+            program.0.try_push(inst).map_err(|_| "Program is too long".to_string())?;
+        }
+        Ok(program)
+    }
+}
+
+impl BfSource {
+    // Proves without running the program that it never halts, by finding a loop that is entered
+    // with a non-zero cell, has no net movement and never changes the tested cell, such as `+[]`
+    // or `+[>+<]`. The cells are known from the start until a loop may run more than once, after
+    // which only the tested cell is known to be zero. On a binary tape, `+` flips the cell.
+    pub fn never_halts(&self, binary_tape: bool) -> bool {
+        let program = &self.0;
+        // The known cells by position relative to the head at the start, or after the last loop.
+        // The other cells are zero if `zeros` is set, and unknown otherwise.
+        let mut cells: HashMap<isize, i64> = HashMap::new();
+        let mut zeros = true;
+        let mut head = 0;
+        // A lower bound of the position of the head on the tape, exact until the first loop that
+        // moves the head.
+        let mut pos = 0;
+        // The starts of the loops whose first iteration is followed.
+        let mut entered = ArrayVec::<usize, BF_MAX_LEN>::new();
+        let nonzero = |value: i64| if binary_tape { value % 2 != 0 } else { value != 0 };
+
+        let mut i = 0;
+        while i < program.len() {
+            let value = cells.get(&head).copied().or(if zeros { Some(0) } else { None });
+            let mut after_loop = None;
+            match program[i] {
+                BfRawInstruction::Plus => {
+                    if let Some(value) = value {
+                        cells.insert(head, value + 1);
+                    }
+                }
+                BfRawInstruction::Minus => {
+                    if let Some(value) = value {
+                        cells.insert(head, value - 1);
+                    }
+                }
+                BfRawInstruction::Print => {}
+                BfRawInstruction::Left => {
+                    // The program may stop with an error.
+                    if pos == 0 {
+                        return false;
+                    }
+                    head -= 1;
+                    pos -= 1;
+                }
+                BfRawInstruction::Right => {
+                    head += 1;
+                    pos += 1;
+                }
+                BfRawInstruction::StartLoop => {
+                    let end = i + Self::loop_len(&program[i..]) - 1;
+                    match value {
+                        Some(value) if !nonzero(value) => {
+                            i = end + 1;
+                            continue;
+                        }
+                        Some(_) => {
+                            if let Some(linear) = BfLinearLoop::new(&program[i + 1..end]) {
+                                let change = linear.tested_change() as i64;
+                                if !nonzero(change) && pos + linear.min_offset >= 0 {
+                                    return true;
+                                }
+                            }
+                            // Follows the first iteration.
+                            entered.push(i);
+                        }
+                        None => {
+                            after_loop = Some(&program[i + 1..end]);
+                            i = end;
+                        }
+                    }
+                }
+                BfRawInstruction::EndLoop => {
+                    after_loop = entered.pop().map(|start| &program[start + 1..i]);
+                }
+            }
+
+            // The loop may run any number of times, and ends with a zero cell. It must not stop
+            // with an error: a linear loop returns the head to the same cell, and a loop without
+            // `<` only moves it right.
+            if let Some(body) = after_loop {
+                match BfLinearLoop::new(body) {
+                    Some(linear) if pos + linear.min_offset < 0 => return false,
+                    Some(_) => {}
+                    None if body.contains(&BfRawInstruction::Left) => return false,
+                    None => pos = 0,
+                }
+                cells.clear();
+                cells.insert(0, 0);
+                zeros = false;
+                head = 0;
+            }
+            i += 1;
+        }
+
+        false
+    }
+
+    // The length of the loop at the start of the instructions.
+    fn loop_len(program: &[BfRawInstruction]) -> usize {
+        let mut depth = 0;
+        for (i, &inst) in program.iter().enumerate() {
+            match inst {
+                BfRawInstruction::StartLoop => depth += 1,
+                BfRawInstruction::EndLoop => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return i + 1;
+            }
+        }
+        unreachable!()
+    }
+}
+
 impl Sized for BfSource {
     fn size(&self) -> usize {
         self.0.len()
     }
+
+    // The self-delimiting code of `BfRanking`. Both instruction sets have four instructions
+    // besides the loops, so the number of programs of a given length is the same.
+    fn bits(&self) -> usize {
+        static BITS: OnceLock<Vec<usize>> = OnceLock::new();
+        let bits = BITS.get_or_init(|| {
+            let ranking = BfRanking::new(false, true);
+            (0..=BF_MAX_LEN).map(|len| ranking.code_bits(len).ceil() as usize).collect()
+        });
+        bits[self.0.len()]
+    }
 }
 
+// Enumerates the programs with balanced loops by length, and lexicographically within the same
+// length. The next program is constructed directly from the previous one, using the number of
+// ways to complete a prefix from `BfRanking`.
 pub struct BfNaiveGenerator {
-    instructions: ArrayVec<BfRawInstruction, 7>,
+    ranking: BfRanking,
     max_len: usize,
-    len: usize,
-    idx: usize,
-    total_for_len: usize,
+    // The last generated program.
+    program: Option<BfSource>,
+}
+
+fn instruction_set(has_minus: bool, has_print: bool) -> ArrayVec<BfRawInstruction, 7> {
+    let mut instructions = ArrayVec::new();
+    instructions.push(BfRawInstruction::Plus);
+    if has_minus {
+        instructions.push(BfRawInstruction::Minus);
+    }
+    if has_print {
+        instructions.push(BfRawInstruction::Print);
+    }
+    instructions.push(BfRawInstruction::Left);
+    instructions.push(BfRawInstruction::Right);
+    instructions.push(BfRawInstruction::StartLoop);
+    instructions.push(BfRawInstruction::EndLoop);
+    instructions
 }
 
 impl BfNaiveGenerator {
     pub fn new(max_len: usize, has_minus: bool, has_print: bool) -> Self {
-        let mut instructions = ArrayVec::new();
-        instructions.push(BfRawInstruction::Plus);
-        if has_minus {
-            instructions.push(BfRawInstruction::Minus);
-        }
-        if has_print {
-            instructions.push(BfRawInstruction::Print);
-        }
-        instructions.push(BfRawInstruction::Left);
-        instructions.push(BfRawInstruction::Right);
-        instructions.push(BfRawInstruction::StartLoop);
-        instructions.push(BfRawInstruction::EndLoop);
+        assert!(max_len <= BF_MAX_LEN);
         BfNaiveGenerator {
+            ranking: BfRanking::new(has_minus, has_print),
+            max_len,
+            program: None,
+        }
+    }
+}
+
+impl Generator<BfSource> for BfNaiveGenerator {
+    fn next(&mut self) -> Option<(BfSource, usize)> {
+        let next = match self.program.as_ref().and_then(|p| self.ranking.successor(p)) {
+            Some(next) => next,
+            None => {
+                // The first program of the next size, which may be over the largest size.
+                let len = self.program.as_ref().map_or(1, |program| program.0.len() + 1);
+                if len > self.max_len {
+                    return None;
+                }
+                self.ranking.first(len)
+            }
+        };
+
+        self.program = Some(next.clone());
+        Some((next, 1))
+    }
+}
+
+// Ranks the programs with balanced loops in the order of `BfNaiveGenerator`, which is the
+// lexicographic order with the instructions ordered as in `instruction_set`.
+#[derive(Clone)]
+pub struct BfRanking {
+    instructions: ArrayVec<BfRawInstruction, 7>,
+    // completions[r][d] is the number of ways to complete a program with r more instructions, if
+    // there are d open loops.
+    completions: Vec<Vec<u128>>,
+}
+
+impl BfRanking {
+    pub fn new(has_minus: bool, has_print: bool) -> Self {
+        let instructions = instruction_set(has_minus, has_print);
+        let simple = instructions.len() as u128 - 2;
+
+        let mut completions = vec![vec![0; BF_MAX_LEN + 2]; BF_MAX_LEN + 1];
+        completions[0][0] = 1;
+        for r in 1..=BF_MAX_LEN {
+            for d in 0..=r {
+                let mut count = simple * completions[r - 1][d] + completions[r - 1][d + 1];
+                if d > 0 {
+                    count += completions[r - 1][d - 1];
+                }
+                completions[r][d] = count;
+            }
+        }
+
+        BfRanking {
+            instructions,
+            completions,
+        }
+    }
+
+    // The number of open loops after the instruction, or None if it closes a loop that is
+    // not open.
+    fn depth_after(depth: usize, inst: BfRawInstruction) -> Option<usize> {
+        match inst {
+            BfRawInstruction::StartLoop => Some(depth + 1),
+            BfRawInstruction::EndLoop => depth.checked_sub(1),
+            _ => Some(depth),
+        }
+    }
+
+    // Appends the lexicographically first completion with `remaining` instructions.
+    fn complete(&self, program: &mut BfSource, mut depth: usize, remaining: usize) {
+        for r in (0..remaining).rev() {
+            for &inst in self.instructions.iter() {
+                if let Some(d) = Self::depth_after(depth, inst) {
+                    if self.completions[r][d] > 0 {
+                        program.0.push(inst);
+                        depth = d;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn first(&self, size: usize) -> BfSource {
+        let mut program = BfSource(ArrayVec::new());
+        self.complete(&mut program, 0, size);
+        program
+    }
+
+    // The next program of the same size, or None if this is the last one.
+    fn successor(&self, program: &BfSource) -> Option<BfSource> {
+        let size = program.0.len();
+        let mut depths = ArrayVec::<usize, BF_MAX_LEN>::new();
+        let mut depth = 0;
+        for &inst in program.0.iter() {
+            depths.push(depth);
+            depth = Self::depth_after(depth, inst).unwrap();
+        }
+
+        for i in (0..size).rev() {
+            let remaining = size - i - 1;
+            let later = self.instructions.iter().skip_while(|&&x| x != program.0[i]).skip(1);
+            for &inst in later {
+                if let Some(d) = Self::depth_after(depths[i], inst) {
+                    if self.completions[remaining][d] > 0 {
+                        let mut next = BfSource(program.0[..i].iter().cloned().collect());
+                        next.0.push(inst);
+                        self.complete(&mut next, d, remaining);
+                        return Some(next);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl Ranking<BfSource> for BfRanking {
+    fn count(&self, size: usize) -> u128 {
+        self.completions[size][0]
+    }
+
+    fn rank(&self, program: &BfSource) -> u128 {
+        let mut index = 0;
+        let mut depth = 0;
+        for (i, &inst) in program.0.iter().enumerate() {
+            let remaining = program.0.len() - i - 1;
+            for &smaller in self.instructions.iter().take_while(|&&x| x != inst) {
+                if let Some(d) = Self::depth_after(depth, smaller) {
+                    index += self.completions[remaining][d];
+                }
+            }
+            depth = Self::depth_after(depth, inst).expect("Unbalanced program");
+        }
+        assert_eq!(depth, 0, "Unbalanced program");
+        index
+    }
+
+    fn max_size(&self) -> usize {
+        BF_MAX_LEN
+    }
+
+    fn check(&self, program: &BfSource) -> Result<(), String> {
+        let mut depth = Some(0);
+        for &inst in program.0.iter() {
+            if !self.instructions.contains(&inst) {
+                let inst = BfSource([inst].into_iter().collect());
+                return Err(format!("Unexpected instruction: {}", inst));
+            }
+            depth = depth.and_then(|depth| Self::depth_after(depth, inst));
+        }
+        match depth {
+            Some(0) => Ok(()),
+            _ => Err("Unbalanced program".to_string()),
+        }
+    }
+
+    fn unrank(&self, size: usize, mut index: u128) -> BfSource {
+        assert!(index < self.count(size));
+        let mut program = BfSource(ArrayVec::new());
+        let mut depth = 0;
+        for i in 0..size {
+            let remaining = size - i - 1;
+            for &inst in self.instructions.iter() {
+                if let Some(d) = Self::depth_after(depth, inst) {
+                    let count = self.completions[remaining][d];
+                    if index < count {
+                        program.0.push(inst);
+                        depth = d;
+                        break;
+                    }
+                    index -= count;
+                }
+            }
+        }
+        program
+    }
+}
+
+// Enumerates only the programs in canonical form, i.e. programs that don't contain
+//
+// * `+-`, `-+` and `><` (and `++` on a binary tape, where `+` flips the bit),
+// * loops at the beginning of the program or right after the end of another loop, which are
+//   never entered since the current cell is known to be zero.
+//
+// Each canonical program is returned with the weight equal to the number of programs of length up
+// to `max_len` that reduce to it, or of the lengths set by `restrict_sizes`. All these programs
+// produce the same output, although the non-canonical ones take more steps.
+//
+// The canonical programs are generated directly by a depth-first search, by length and
+// lexicographically within the same length. A program reduces to a canonical one if it consists
+// of its instructions with null segments between them, which reduce to nothing after the
+// preceding instruction. So the weight is computed from the numbers of null segments by length.
+pub struct BfCanonicalGenerator {
+    instructions: ArrayVec<BfRawInstruction, 7>,
+    binary_tape: bool,
+    max_len: usize,
+    // The lengths of the programs counted in the weights.
+    sizes: RangeInclusive<usize>,
+    // nulls[i][m] is the number of null segments of length m after the instruction i, and the
+    // last row is for the segments at the start of the program.
+    nulls: Vec<Vec<usize>>,
+    // The length of the generated programs, and the current program as indices of instructions.
+    len: usize,
+    choices: ArrayVec<usize, BF_MAX_LEN>,
+    // The open loops and the weights by the total length of the null segments, after each prefix
+    // of the current program.
+    depths: ArrayVec<usize, { BF_MAX_LEN + 1 }>,
+    weights: Vec<Vec<usize>>,
+}
+
+impl BfCanonicalGenerator {
+    pub fn new(max_len: usize, has_minus: bool, has_print: bool, binary_tape: bool) -> Self {
+        assert!(max_len <= BF_MAX_LEN);
+        let instructions = instruction_set(has_minus, has_print);
+        let balanced = BfRanking::new(has_minus, has_print);
+        let start = instructions.len();
+
+        // A null segment is a sequence of blocks that leave the same last instruction: a loop
+        // that is never entered, or an instruction followed by a null segment and by the
+        // instruction that cancels it.
+        let mut nulls = vec![vec![0; max_len + 1]; start + 1];
+        for m in 0..=max_len {
+            for t in 0..=start {
+                let last = instructions.get(t).copied();
+                if m == 0 {
+                    nulls[t][0] = 1;
+                    continue;
+                }
+                let mut count = 0;
+                for b in 2..=m {
+                    let mut blocks = 0;
+                    if matches!(last, None | Some(BfRawInstruction::EndLoop)) {
+                        blocks += balanced.completions[b - 2][0] as usize;
+                    }
+                    for (x, &inst) in instructions.iter().enumerate() {
+                        if last.is_some_and(|last| Self::cancels(last, inst, binary_tape)) {
+                            continue;
+                        }
+                        let undo = |&&y: &&BfRawInstruction| Self::cancels(inst, y, binary_tape);
+                        blocks += instructions.iter().filter(undo).count() * nulls[x][b - 2];
+                    }
+                    count += blocks * nulls[t][m - b];
+                }
+                nulls[t][m] = count;
+            }
+        }
+
+        BfCanonicalGenerator {
             instructions,
+            binary_tape,
             max_len,
+            sizes: 0..=max_len,
+            nulls,
             len: 0,
-            idx: 0,
-            total_for_len: 0,
+            choices: ArrayVec::new(),
+            depths: ArrayVec::new(),
+            weights: Vec::new(),
         }
     }
 
-    fn inc_len(&mut self) {
-        self.len += 1;
-        self.total_for_len = 1;
-        for _ in 0..self.len {
-            self.total_for_len *= self.instructions.len();
+    fn cancels(a: BfRawInstruction, b: BfRawInstruction, binary_tape: bool) -> bool {
+        use BfRawInstruction::*;
+        match (a, b) {
+            (Plus, Minus) | (Minus, Plus) | (Right, Left) => true,
+            (Plus, Plus) | (Minus, Minus) => binary_tape,
+            _ => false,
         }
-        self.idx = 0;
+    }
+
+    // The number of open loops after appending the instruction to the current program, if the
+    // result is canonical and can be completed to the current length.
+    fn append(&self, inst: BfRawInstruction) -> Option<usize> {
+        let last = self.choices.last().map(|&i| self.instructions[i]);
+        let dead_loop = matches!(last, None | Some(BfRawInstruction::EndLoop));
+        if inst == BfRawInstruction::StartLoop && dead_loop
+            || last.is_some_and(|last| Self::cancels(last, inst, self.binary_tape))
+        {
+            return None;
+        }
+        let depth = BfRanking::depth_after(*self.depths.last().unwrap(), inst)?;
+        (depth < self.len - self.choices.len()).then_some(depth)
+    }
+
+    // Starts the programs of the next length.
+    fn next_len(&mut self) {
+        self.len += 1;
+        self.depths.clear();
+        self.depths.push(0);
+        let extra = self.max_len.saturating_sub(self.len);
+        self.weights = vec![self.nulls[self.instructions.len()][..=extra].to_vec()];
+    }
+
+    // The number of programs with the lengths in the range that reduce to a program of the given
+    // length, from their numbers by the total length of the null segments.
+    fn weight(&self, len: usize, weights: &[usize]) -> usize {
+        (0..weights.len()).filter(|m| self.sizes.contains(&(len + m))).map(|m| weights[m]).sum()
     }
 }
 
-impl Generator<BfSource> for BfNaiveGenerator {
-    fn next(&mut self) -> Option<(BfSource, usize)> {
-        let mut program = BfSource(ArrayVec::new());
-        'idx: loop {
-            program.0.clear();
+impl Generator<BfSource> for BfCanonicalGenerator {
+    // The programs that only stand for programs out of the range are skipped.
+    fn restrict_sizes(&mut self, sizes: &RangeInclusive<usize>) -> bool {
+        self.sizes = sizes.clone();
+        true
+    }
 
-            if self.idx >= self.total_for_len {
-                self.inc_len();
+    fn next(&mut self) -> Option<(BfSource, usize)> {
+        if self.len == 0 {
+            // The empty program stands for the null programs, which are not empty.
+            self.next_len();
+            let weight = self.weight(1, &self.nulls[self.instructions.len()][1..]);
+            if weight > 0 {
+                return Some((BfSource(ArrayVec::new()), weight));
             }
+        }
 
+        let mut choice = 0;
+        loop {
             if self.len > self.max_len {
                 return None;
             }
+            // Continues after the last program, which has the full length.
+            if self.choices.len() == self.len {
+                choice = self.choices.pop().unwrap() + 1;
+                self.depths.pop();
+                self.weights.pop();
+            }
+            let next = (choice..self.instructions.len())
+                .find_map(|i| self.append(self.instructions[i]).map(|depth| (i, depth)));
+            let Some((i, depth)) = next else {
+                match self.choices.pop() {
+                    Some(last) => {
+                        choice = last + 1;
+                        self.depths.pop();
+                        self.weights.pop();
+                    }
+                    None => {
+                        choice = 0;
+                        self.next_len();
+                    }
+                }
+                continue;
+            };
+
+            let nulls = &self.nulls[i];
+            let previous = self.weights.last().unwrap();
+            let weights = (0..previous.len())
+                .map(|m| (0..=m).map(|k| previous[k] * nulls[m - k]).sum())
+                .collect();
+            self.choices.push(i);
+            self.depths.push(depth);
+            self.weights.push(weights);
+            choice = 0;
 
-            let mut idx = self.idx;
-            self.idx += 1;
+            if self.choices.len() == self.len {
+                let weight = self.weight(self.len, self.weights.last().unwrap());
+                if weight > 0 {
+                    let program = self.choices.iter().map(|&i| self.instructions[i]).collect();
+                    return Some((BfSource(program), weight));
+                }
+            }
+        }
+    }
+}
+
+// A loop whose body only changes cells and moves the head, with no net movement, such as `[-]`
+// or `[->+<]`. The effect of any number of iterations can be computed at once.
+pub struct BfLinearLoop {
+    // The change of the cells during an iteration by offset from the head, with `+` counted as 1
+    // and `-` as -1.
+    pub changes: Vec<(isize, i32)>,
+    pub prints: usize,
+    // The leftmost offset visited by the head.
+    pub min_offset: isize,
+    pub max_offset: isize,
+    // The number of steps of an iteration, including the end of the loop and the next test.
+    pub iteration_steps: usize,
+}
+
+impl BfLinearLoop {
+    // Returns None if the loop isn't linear.
+    pub fn new(body: &[BfRawInstruction]) -> Option<Self> {
+        let mut changes: Vec<(isize, i32)> = Vec::new();
+        let mut prints = 0;
+        let mut offset = 0;
+        let mut min_offset = 0;
+        let mut max_offset = 0;
+
+        for &inst in body {
+            let change = match inst {
+                BfRawInstruction::Plus => 1,
+                BfRawInstruction::Minus => -1,
+                BfRawInstruction::Print => {
+                    prints += 1;
+                    continue;
+                }
+                BfRawInstruction::Left => {
+                    offset -= 1;
+                    min_offset = min_offset.min(offset);
+                    continue;
+                }
+                BfRawInstruction::Right => {
+                    offset += 1;
+                    max_offset = max_offset.max(offset);
+                    continue;
+                }
+                BfRawInstruction::StartLoop | BfRawInstruction::EndLoop => return None,
+            };
+            match changes.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, c)) => *c += change,
+                None => changes.push((offset, change)),
+            }
+        }
+
+        if offset != 0 {
+            return None;
+        }
+        Some(BfLinearLoop {
+            changes,
+            prints,
+            min_offset,
+            max_offset,
+            iteration_steps: body.len() + 2,
+        })
+    }
+
+    // The change of the tested cell during an iteration.
+    pub fn tested_change(&self) -> i32 {
+        self.changes.iter().find(|(o, _)| *o == 0).map_or(0, |&(_, c)| c)
+    }
+
+    // The linear loop starting at `source[start]`, if there is one.
+    pub fn at(source: &[BfRawInstruction], start: usize) -> Option<Self> {
+        let body = &source[start + 1..];
+        let end = body.iter().position(|&inst| {
+            inst == BfRawInstruction::StartLoop || inst == BfRawInstruction::EndLoop
+        })?;
+        if body[end] == BfRawInstruction::EndLoop {
+            Self::new(&body[..end])
+        } else {
+            None
+        }
+    }
 
-            let mut open_loops = 0;
+    // The number of steps of the loop if it is entered and runs `iterations` times.
+    pub fn steps(&self, iterations: u64) -> usize {
+        let steps = iterations.saturating_mul(self.iteration_steps as u64).saturating_add(1);
+        steps.try_into().unwrap_or(usize::MAX)
+    }
+}
+
+// A program compiled for one of the Brainfuck systems, whose instructions execute runs of source
+// instructions and loops at once. The steps are still counted by the source instructions.
+pub struct BfCompiled<I> {
+    // Linear loops and scan loops may be preceded by an instruction that executes them.
+    pub code: ArrayVec<I, { 2 * BF_MAX_LEN }>,
+    pub loops: Vec<BfLinearLoop>,
+}
+
+impl<I> BfCompiled<I> {
+    pub fn new() -> Self {
+        BfCompiled {
+            code: ArrayVec::new(),
+            loops: Vec::new(),
+        }
+    }
+}
+
+// Counts the steps of the executed instructions, and stops the execution when the limit is
+// reached.
+pub fn spend<O>(step: &mut usize, steps: usize, max_steps: usize) -> Result<(), ProgResult<O>> {
+    *step = step.saturating_add(steps);
+    if *step >= max_steps {
+        Err(ProgResult::Timeout)
+    } else {
+        Ok(())
+    }
+}
+
+// A tape of a Brainfuck machine, with the cells from position 0 up to the last accessed one.
+pub trait BfTape: Clone {
+    fn len(&self) -> usize;
+
+    // Whether the cells from `pos` to the end are the same as the cells of the other tape from
+    // `other_pos` to its end.
+    fn same_suffix(&self, pos: usize, other: &Self, other_pos: usize) -> bool;
+}
+
+impl<T: Clone + PartialEq> BfTape for Vec<T> {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn same_suffix(&self, pos: usize, other: &Self, other_pos: usize) -> bool {
+        self[pos..] == other[other_pos..]
+    }
+}
+
+impl BfTape for BitTape {
+    fn len(&self) -> usize {
+        self.end() as usize
+    }
+
+    fn same_suffix(&self, pos: usize, other: &Self, other_pos: usize) -> bool {
+        let len = BfTape::len(self) - pos;
+        len == BfTape::len(other) - other_pos
+            && self.same_cells(pos as isize, other, other_pos as isize, len)
+    }
+}
+
+// A configuration of a Brainfuck machine at the test of a loop. The output is not included, since
+// it doesn't affect the execution.
+struct BfConfiguration<T> {
+    ip: usize,
+    pos: usize,
+    tape: T,
+}
+
+// Proves that a Brainfuck machine never halts from the configurations at the tests of loops. A
+// configuration is saved at exponentially spaced tests, as in Brent's cycle detection, so that
+// every cycle is eventually found.
+//
+// The cells beyond the tape are zero and have never been accessed. If a later test of the same
+// loop has the head moved right by some distance (possibly zero), the same number of cells from
+// the head to the end of the tape, and the same cells from the leftmost cell accessed in between
+// up to the end, then the machine repeats the same computation shifted by the distance forever.
+pub struct BfCycleDetector<T> {
+    tests: usize,
+    saved: Option<BfConfiguration<T>>,
+    // The leftmost cell accessed since the configuration was saved.
+    leftmost: usize,
+}
+
+impl<T: BfTape> BfCycleDetector<T> {
+    pub fn new() -> Self {
+        BfCycleDetector {
+            tests: 0,
+            saved: None,
+            leftmost: 0,
+        }
+    }
+
+    // Registers a move of the head to the left, or an access to a cell left of the head.
+    pub fn access(&mut self, pos: usize) {
+        self.leftmost = self.leftmost.min(pos);
+    }
+
+    // Registers a test of the loop at `ip`. Returns the behaviour if the machine never halts.
+    pub fn test(&mut self, ip: usize, pos: usize, tape: &T) -> Option<Behavior> {
+        if let Some(saved) = &self.saved {
+            if saved.ip == ip
+                && saved.pos <= pos
+                && saved.tape.len() - saved.pos == tape.len() - pos
+                && saved.tape.same_suffix(self.leftmost, tape, self.leftmost + pos - saved.pos)
+            {
+                if saved.pos == pos {
+                    return Some(Behavior::Cycler);
+                }
+                return Some(Behavior::TranslatedCycler);
+            }
+        }
+
+        self.tests += 1;
+        if self.tests.is_power_of_two() {
+            self.saved = Some(BfConfiguration { ip, pos, tape: tape.clone() });
+            self.leftmost = pos;
+        }
+        None
+    }
+}
+
+// A Brainfuck system that can execute a program incrementally, as long as there are no loops.
+pub trait BfIncremental: System<Program = BfSource> {
+    type State: Clone;
+
+    fn ranking(&self) -> BfRanking;
+
+    fn initial_state(&self) -> Self::State;
+
+    // Executes a single instruction that is not a loop. Returns the result if the execution is
+    // stopped, which is then the same for any program starting with the executed instructions.
+    fn step(
+        &self,
+        state: &mut Self::State,
+        inst: BfRawInstruction,
+        max_steps: usize,
+    ) -> Option<ProgResult<Self::Output>>;
+
+    // Executes the program starting from the instruction `ip`, given the state before it. There
+    // are no loops before `ip`.
+    fn resume(
+        &self,
+        program: &BfSource,
+        state: Self::State,
+        ip: usize,
+        max_steps: usize,
+    ) -> ProgResult<Self::Output>;
+}
+
+enum BfPrefixNode<S: BfIncremental> {
+    // A prefix of the programs, with the state of the machine before the first loop and the
+    // position of the loop, or the length of the prefix if there are no loops.
+    Prefix {
+        prefix: BfSource,
+        depth: usize,
+        state: S::State,
+        ip: usize,
+    },
+    // The programs of a given size starting with a prefix, which all have the same result, as
+    // the first of them and their number.
+    Determined {
+        program: BfSource,
+        result: ProgResult<S::Output>,
+        weight: usize,
+    },
+}
+
+// Enumerates and executes all the programs up to `max_len` in depth-first order. The machine
+// state is shared between the programs with a common loop-free prefix, and if the execution of
+// such prefix is stopped by an error or a timeout, all the programs starting with it are
+// returned at once. They are returned by size, as the first program of each size with the number
+// of programs of that size as the weight, so that the statistics by size are kept.
+pub struct BfPrefixSearch<'a, S: BfIncremental> {
+    system: &'a S,
+    ranking: BfRanking,
+    max_len: usize,
+    max_steps: usize,
+    stack: Vec<BfPrefixNode<S>>,
+}
+
+impl<'a, S: BfIncremental> BfPrefixSearch<'a, S> {
+    pub fn new(system: &'a S, max_len: usize, max_steps: usize) -> Self {
+        assert!(max_len <= BF_MAX_LEN);
+        let root = BfPrefixNode::Prefix {
+            prefix: BfSource(ArrayVec::new()),
+            depth: 0,
+            state: system.initial_state(),
+            ip: 0,
+        };
+        BfPrefixSearch {
+            system,
+            ranking: system.ranking(),
+            max_len,
+            max_steps,
+            stack: vec![root],
+        }
+    }
+
+    fn push_children(&mut self, prefix: &BfSource, depth: usize, state: &S::State, ip: usize) {
+        let len = prefix.0.len();
+        if len == self.max_len {
+            return;
+        }
+
+        let mut children = Vec::new();
+        for &inst in self.ranking.instructions.iter() {
+            let Some(d) = BfRanking::depth_after(depth, inst) else {
+                continue;
+            };
+            if d > self.max_len - len - 1 {
+                continue;
+            }
+
+            let mut child = prefix.clone();
+            child.0.push(inst);
+            let mut child_state = state.clone();
+
+            if ip == len && inst != BfRawInstruction::StartLoop {
+                if let Some(result) = self.system.step(&mut child_state, inst, self.max_steps) {
+                    for remaining in 0..self.max_len - len {
+                        let weight = self.ranking.completions[remaining][0] as usize;
+                        if weight == 0 {
+                            continue;
+                        }
+                        let mut program = child.clone();
+                        self.ranking.complete(&mut program, 0, remaining);
+                        children.push(BfPrefixNode::Determined {
+                            program,
+                            result: result.clone(),
+                            weight,
+                        });
+                    }
+                    continue;
+                }
+            }
+
+            let child_ip = if ip == len && inst != BfRawInstruction::StartLoop {
+                len + 1
+            } else {
+                ip
+            };
+            children.push(BfPrefixNode::Prefix {
+                prefix: child,
+                depth: d,
+                state: child_state,
+                ip: child_ip,
+            });
+        }
+
+        self.stack.extend(children.into_iter().rev());
+    }
+
+    pub fn next(&mut self) -> Option<(BfSource, ProgResult<S::Output>, usize)> {
+        loop {
+            match self.stack.pop()? {
+                BfPrefixNode::Determined {
+                    program,
+                    result,
+                    weight,
+                } => return Some((program, result, weight)),
+                BfPrefixNode::Prefix {
+                    prefix,
+                    depth,
+                    state,
+                    ip,
+                } => {
+                    self.push_children(&prefix, depth, &state, ip);
+                    if depth == 0 && !prefix.0.is_empty() {
+                        let result = self.system.resume(&prefix, state, ip, self.max_steps);
+                        return Some((prefix, result, 1));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A cell of the tape of the reference interpreter.
+#[cfg(test)]
+pub trait BfCell: Copy + Default {
+    fn plus(self) -> Self;
+    fn minus(self) -> Self;
+    fn is_zero(self) -> bool;
+}
+
+// A cell of a binary tape, which `+` flips.
+#[cfg(test)]
+impl BfCell for bool {
+    fn plus(self) -> Self {
+        !self
+    }
+
+    fn minus(self) -> Self {
+        !self
+    }
+
+    fn is_zero(self) -> bool {
+        !self
+    }
+}
+
+#[cfg(test)]
+impl BfCell for i32 {
+    fn plus(self) -> Self {
+        self + 1
+    }
+
+    fn minus(self) -> Self {
+        self - 1
+    }
+
+    fn is_zero(self) -> bool {
+        self == 0
+    }
+}
+
+// The unoptimized interpreter, one step per instruction. When the program ends, the result is
+// computed from the tape, the number of executed `.` and the number of steps.
+#[cfg(test)]
+pub fn reference_execute<C: BfCell, O>(
+    source: &BfSource,
+    max_steps: usize,
+    result: impl FnOnce(&[C], u64, usize) -> ProgResult<O>,
+) -> ProgResult<O> {
+    let program = &source.0;
+    let mut tape = vec![C::default()];
+    let mut prints = 0;
+    let mut pos = 0;
+    let mut ip = 0;
+    let mut step = 0;
+
+    while step < max_steps && ip < program.len() {
+        if pos >= tape.len() {
+            tape.resize(pos + 1, C::default());
+        }
+        match program[ip] {
+            BfRawInstruction::Plus => tape[pos] = tape[pos].plus(),
+            BfRawInstruction::Minus => tape[pos] = tape[pos].minus(),
+            BfRawInstruction::Print => prints += 1,
+            BfRawInstruction::Left if pos == 0 => return ProgResult::Error,
+            BfRawInstruction::Left => pos -= 1,
+            BfRawInstruction::Right => pos += 1,
+            BfRawInstruction::StartLoop if tape[pos].is_zero() => ip = matching(program, ip),
+            BfRawInstruction::EndLoop => ip = matching(program, ip) - 1,
+            BfRawInstruction::StartLoop => {}
+        }
+        ip += 1;
+        step += 1;
+    }
+
+    if step >= max_steps {
+        ProgResult::Timeout
+    } else {
+        result(&tape, prints, step)
+    }
+}
+
+// The position of the bracket matching the one at `ip`.
+#[cfg(test)]
+fn matching(program: &[BfRawInstruction], ip: usize) -> usize {
+    let mut depth = 0;
+    let mut i = ip;
+    loop {
+        match program[i] {
+            BfRawInstruction::StartLoop => depth += 1,
+            BfRawInstruction::EndLoop => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return i;
+        }
+        if program[ip] == BfRawInstruction::StartLoop {
+            i += 1;
+        } else {
+            i -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The original generator, which enumerates all the strings and skips the ones with
+    // unbalanced loops.
+    struct BfRejectionGenerator {
+        instructions: ArrayVec<BfRawInstruction, 7>,
+        max_len: usize,
+        len: usize,
+        idx: usize,
+        total_for_len: usize,
+    }
+
+    impl BfRejectionGenerator {
+        fn new(max_len: usize, has_minus: bool, has_print: bool) -> Self {
+            BfRejectionGenerator {
+                instructions: instruction_set(has_minus, has_print),
+                max_len,
+                len: 0,
+                idx: 0,
+                total_for_len: 0,
+            }
+        }
+
+        fn inc_len(&mut self) {
+            self.len += 1;
+            self.total_for_len = 1;
             for _ in 0..self.len {
-                let instruction = self.instructions[idx % self.instructions.len()];
-                idx /= self.instructions.len();
-                match instruction {
-                    BfRawInstruction::EndLoop => open_loops += 1,
-                    BfRawInstruction::StartLoop => {
-                        if open_loops == 0 {
-                            continue 'idx;
-                        };
-                        open_loops -= 1;
+                self.total_for_len *= self.instructions.len();
+            }
+            self.idx = 0;
+        }
+    }
+
+    impl Generator<BfSource> for BfRejectionGenerator {
+        fn next(&mut self) -> Option<(BfSource, usize)> {
+            let mut program = BfSource(ArrayVec::new());
+            'idx: loop {
+                program.0.clear();
+
+                if self.idx >= self.total_for_len {
+                    self.inc_len();
+                }
+
+                if self.len > self.max_len {
+                    return None;
+                }
+
+                let mut idx = self.idx;
+                self.idx += 1;
+
+                let mut open_loops = 0;
+                for _ in 0..self.len {
+                    let instruction = self.instructions[idx % self.instructions.len()];
+                    idx /= self.instructions.len();
+                    match instruction {
+                        BfRawInstruction::EndLoop => open_loops += 1,
+                        BfRawInstruction::StartLoop => {
+                            if open_loops == 0 {
+                                continue 'idx;
+                            };
+                            open_loops -= 1;
+                        }
+                        _ => (),
                     }
+                    program.0.push(instruction);
+                }
+
+                if open_loops == 0 {
+                    program.0.reverse();
+                    return Some((program, 1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_rejection() {
+        for (has_minus, has_print) in [(false, true), (true, false)] {
+            let mut gen1 = BfNaiveGenerator::new(7, has_minus, has_print);
+            let mut gen2 = BfRejectionGenerator::new(7, has_minus, has_print);
+            loop {
+                let p1 = gen1.next().map(|(p, _)| p);
+                let p2 = gen2.next().map(|(p, _)| p);
+                assert_eq!(p1, p2);
+                if p1.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Reduces a program to its canonical form, as the original canonical generator did for
+    // every program.
+    fn reduce(source: &BfSource, binary_tape: bool) -> BfSource {
+        let mut program = BfSource(ArrayVec::new());
+        let mut skip_depth = 0;
+
+        for &inst in source.0.iter() {
+            if skip_depth > 0 {
+                match inst {
+                    BfRawInstruction::StartLoop => skip_depth += 1,
+                    BfRawInstruction::EndLoop => skip_depth -= 1,
                     _ => (),
                 }
-                program.0.push(instruction);
+                continue;
+            }
+
+            match (program.0.last(), inst) {
+                (None, BfRawInstruction::StartLoop)
+                | (Some(BfRawInstruction::EndLoop), BfRawInstruction::StartLoop) => {
+                    skip_depth = 1;
+                }
+                (Some(&last), _) if BfCanonicalGenerator::cancels(last, inst, binary_tape) => {
+                    program.0.pop();
+                }
+                _ => program.0.push(inst),
+            }
+        }
+
+        program
+    }
+
+    #[test]
+    fn canonical_weights() {
+        for (has_minus, has_print, binary_tape) in [(false, true, true), (true, false, false)] {
+            let mut weights = HashMap::new();
+            let mut naive = BfNaiveGenerator::new(7, has_minus, has_print);
+            while let Some((program, weight)) = naive.next() {
+                *weights.entry(reduce(&program, binary_tape)).or_insert(0) += weight;
+            }
+            let mut expected: Vec<_> = weights.into_iter().collect();
+            expected.sort_by(|a, b| a.0 .0.len().cmp(&b.0 .0.len()).then_with(|| a.0.cmp(&b.0)));
+
+            let mut gen = BfCanonicalGenerator::new(7, has_minus, has_print, binary_tape);
+            let mut programs = Vec::new();
+            while let Some(program) = gen.next() {
+                programs.push(program);
             }
+            assert_eq!(programs, expected);
+        }
+    }
+
+    #[test]
+    fn never_halts() {
+        let never_halts = |source: &str, binary_tape| {
+            source.parse::<BfSource>().unwrap().never_halts(binary_tape)
+        };
+
+        let sources = ["+[]", "+[>+<]", ">+[<>]", "+[[]]", "+[>]+[]", "+[>+<[]]", "+[-]+[>-<]"];
+        for source in sources {
+            assert!(never_halts(source, false), "{}", source);
+        }
+        // A loop with `<` may stop with an error before the infinite loop.
+        for source in ["[]", "+[<>]", "+[-]", "+[>]<[]", "+[+]", "+>+[<]+[]"] {
+            assert!(!never_halts(source, false), "{}", source);
+        }
+        assert!(never_halts("+++[]", true));
+        assert!(!never_halts("++[]", true));
+        assert!(!never_halts("+[+]", true));
+    }
+
+    #[test]
+    fn naive_generator_stops_at_max_len() {
+        let mut gen = BfNaiveGenerator::new(BF_MAX_LEN, false, true);
+        let count = gen.ranking.count(BF_MAX_LEN);
+        gen.program = Some(gen.ranking.unrank(BF_MAX_LEN, count - 2));
+        assert!(gen.next().is_some());
+        assert!(gen.next().is_none());
+    }
+
+    #[test]
+    fn ranking_checks_programs() {
+        let ranking = BfRanking::new(false, true);
+        assert!(ranking.check(&"+[>.]".parse().unwrap()).is_ok());
+        for source in ["+-", "+[", "]+[", "[]]"] {
+            assert!(ranking.check(&source.parse().unwrap()).is_err(), "{}", source);
+        }
+        assert!(BfRanking::new(true, false).check(&"+.".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn ranking_matches_naive_order() {
+        for (has_minus, has_print) in [(false, true), (true, false)] {
+            let ranking = BfRanking::new(has_minus, has_print);
+            let mut gen = BfNaiveGenerator::new(6, has_minus, has_print);
+            let mut size = 0;
+            let mut index = 0;
 
-            if open_loops == 0 {
-                program.0.reverse();
-                return Some((program, 1));
+            while let Some((program, _)) = gen.next() {
+                if program.size() != size {
+                    if size > 0 {
+                        assert_eq!(index, ranking.count(size));
+                    }
+                    size = program.size();
+                    index = 0;
+                }
+                assert_eq!(ranking.rank(&program), index);
+                assert_eq!(ranking.unrank(size, index).to_string(), program.to_string());
+                index += 1;
             }
         }
     }
@@ -0,0 +1,310 @@
+use crate::bit_seq::BitSeq;
+use crate::def::{gamma_len, Generator, ProgResult, Ranking, Sized, System};
+
+// Circuits have at most 6 inputs, so that a truth table fits into u64.
+const MAX_INPUTS: usize = 6;
+
+// A NAND gate. Wires 0..ninputs are the inputs of the circuit, wire ninputs + i is the output
+// of gate i. Since NAND is symmetric, only gates with `left <= right` are generated.
+#[derive(Clone, Copy, Debug)]
+struct Gate {
+    left: usize,
+    right: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct CircuitProgram {
+    ninputs: usize,
+    gates: Vec<Gate>,
+}
+
+impl CircuitProgram {
+    fn wire_name(&self, wire: usize) -> String {
+        if wire < self.ninputs {
+            format!("x{}", wire)
+        } else {
+            format!("g{}", wire - self.ninputs)
+        }
+    }
+}
+
+impl Sized for CircuitProgram {
+    fn size(&self) -> usize {
+        self.gates.len()
+    }
+
+    // The Elias gamma code of the number of gates + 1, followed by the index of the circuit among
+    // the circuits with the same number of gates.
+    fn bits(&self) -> usize {
+        let choices: f64 = (0..self.gates.len())
+            .map(|i| {
+                let nwires = self.ninputs + i;
+                ((nwires * (nwires + 1) / 2) as f64).log2()
+            })
+            .sum();
+        gamma_len(self.gates.len() + 1) + choices.ceil() as usize
+    }
+}
+
+impl std::fmt::Display for CircuitProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.gates.is_empty() {
+            return write!(f, "{}", self.wire_name(self.ninputs - 1));
+        }
+        for (i, gate) in self.gates.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(
+                f,
+                "g{}=nand({},{})",
+                i,
+                self.wire_name(gate.left),
+                self.wire_name(gate.right)
+            )?;
+        }
+        std::fmt::Result::Ok(())
+    }
+}
+
+// Enumerates circuits by the number of gates.
+pub struct CircuitGenerator {
+    ninputs: usize,
+    max_gates: usize,
+    ngates: usize,
+    index_within_ngates: usize,
+    total_for_ngates: usize,
+}
+
+impl CircuitGenerator {
+    fn new(ninputs: usize, max_gates: usize) -> Self {
+        CircuitGenerator {
+            ninputs,
+            max_gates,
+            ngates: 0,
+            index_within_ngates: 0,
+            total_for_ngates: 1,
+        }
+    }
+
+    fn increment_ngates(&mut self) {
+        self.ngates += 1;
+        self.index_within_ngates = 0;
+        self.total_for_ngates = 1;
+        for i in 0..self.ngates {
+            let nwires = self.ninputs + i;
+            self.total_for_ngates *= nwires * (nwires + 1) / 2;
+        }
+    }
+
+    fn gate_from_idx(nwires: usize, idx: &mut usize) -> Gate {
+        let mut pair_idx = *idx % (nwires * (nwires + 1) / 2);
+        *idx /= nwires * (nwires + 1) / 2;
+
+        let mut right = 0;
+        while pair_idx > right {
+            right += 1;
+            pair_idx -= right;
+        }
+
+        Gate {
+            left: pair_idx,
+            right,
+        }
+    }
+}
+
+impl Generator<CircuitProgram> for CircuitGenerator {
+    fn next(&mut self) -> Option<(CircuitProgram, usize)> {
+        if self.index_within_ngates >= self.total_for_ngates {
+            self.increment_ngates();
+        }
+        if self.ngates > self.max_gates {
+            return None;
+        }
+
+        let mut idx = self.index_within_ngates;
+        self.index_within_ngates += 1;
+
+        let mut program = CircuitProgram {
+            ninputs: self.ninputs,
+            gates: Vec::new(),
+        };
+        for i in 0..self.ngates {
+            program.gates.push(Self::gate_from_idx(self.ninputs + i, &mut idx));
+        }
+
+        Some((program, 1))
+    }
+}
+
+// Ranks the circuits in the order of `CircuitGenerator`, where the gates are the digits of the
+// index with the first gate as the lowest one.
+#[derive(Clone)]
+pub struct CircuitRanking {
+    ninputs: usize,
+    // The number of circuits by the number of gates, as long as it fits.
+    counts: Vec<u128>,
+}
+
+impl CircuitRanking {
+    pub fn new(ninputs: usize) -> Self {
+        let mut counts = vec![1];
+        let mut count: u128 = 1;
+        for i in 0.. {
+            let Some(next) = count.checked_mul(Self::choices(ninputs + i)) else {
+                break;
+            };
+            count = next;
+            counts.push(count);
+        }
+        CircuitRanking { ninputs, counts }
+    }
+
+    // The number of gates over the given number of wires.
+    fn choices(nwires: usize) -> u128 {
+        (nwires * (nwires + 1) / 2) as u128
+    }
+}
+
+impl Ranking<CircuitProgram> for CircuitRanking {
+    fn count(&self, ngates: usize) -> u128 {
+        self.counts[ngates]
+    }
+
+    fn rank(&self, program: &CircuitProgram) -> u128 {
+        program.gates.iter().enumerate().rev().fold(0, |index, (i, gate)| {
+            let pair = gate.right * (gate.right + 1) / 2 + gate.left;
+            index * Self::choices(self.ninputs + i) + pair as u128
+        })
+    }
+
+    fn unrank(&self, ngates: usize, mut index: u128) -> CircuitProgram {
+        assert!(index < self.count(ngates));
+        let gates = (0..ngates)
+            .map(|i| {
+                let choices = Self::choices(self.ninputs + i);
+                let mut idx = (index % choices) as usize;
+                index /= choices;
+                CircuitGenerator::gate_from_idx(self.ninputs + i, &mut idx)
+            })
+            .collect();
+        CircuitProgram {
+            ninputs: self.ninputs,
+            gates,
+        }
+    }
+
+    fn max_size(&self) -> usize {
+        self.counts.len() - 1
+    }
+
+    fn check(&self, program: &CircuitProgram) -> Result<(), String> {
+        if program.ninputs != self.ninputs {
+            Err(format!("The circuits have {} inputs", self.ninputs))
+        } else if program.size() > self.max_size() {
+            Err(format!("The circuits have at most {} gates", self.max_size()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Boolean circuits of NAND gates over a fixed number of inputs. The output is the truth table of
+// the last wire, with row x corresponding to the inputs x_j = (x >> j) & 1.
+pub struct Circuit {
+    ninputs: usize,
+}
+
+impl Circuit {
+    pub fn new(ninputs: usize) -> Self {
+        assert!((1..=MAX_INPUTS).contains(&ninputs));
+        Circuit { ninputs }
+    }
+}
+
+impl System for Circuit {
+    type Output = BitSeq;
+    type Program = CircuitProgram;
+
+    fn generate(&self, limit: usize) -> impl Generator<CircuitProgram> {
+        CircuitGenerator::new(self.ninputs, limit)
+    }
+
+    // The truth table is computed for all rows at once, every wire is a bit mask over the rows.
+    // Each gate evaluation is counted as a step.
+    fn execute(&self, program: &CircuitProgram, max_steps: usize) -> ProgResult<BitSeq> {
+        let rows = 1 << program.ninputs;
+        let steps = program.gates.len();
+        if steps > max_steps {
+            return ProgResult::Timeout;
+        }
+
+        let mut wires: Vec<u64> = Vec::with_capacity(program.ninputs + program.gates.len());
+        for j in 0..program.ninputs {
+            let mut mask = 0;
+            for x in 0..rows {
+                if (x >> j) & 1 != 0 {
+                    mask |= 1 << x;
+                }
+            }
+            wires.push(mask);
+        }
+        for gate in program.gates.iter() {
+            wires.push(!(wires[gate.left] & wires[gate.right]));
+        }
+
+        let result = *wires.last().unwrap();
+        let output = BitSeq((0..rows).map(|x| (result >> x) & 1 != 0).collect());
+
+        ProgResult::Out { output, steps }
+    }
+
+    fn valid_output(_o: &BitSeq) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stat::Stat;
+
+    #[test]
+    fn all_two_input_functions() {
+        let comp = Circuit::new(2);
+        let mut gen = comp.generate(5);
+        let mut stat: Stat<Circuit> = Stat::new();
+
+        while let Some((program, weight)) = gen.next() {
+            let result = comp.execute(&program, 100);
+            stat.register(&program, &result, weight);
+        }
+
+        assert_eq!(stat.outputs.len(), 16);
+        // XOR requires 4 NAND gates.
+        let xor = BitSeq(vec![false, true, true, false]);
+        assert_eq!(stat.outputs[&xor].min_program.size(), 4);
+    }
+
+    // The bit lengths are those of a prefix-free code, so they satisfy the Kraft inequality.
+    #[test]
+    fn kraft_inequality() {
+        let comp = Circuit::new(2);
+        let mut gen = comp.generate(3);
+        let mut total = 0.0;
+
+        while let Some((program, _)) = gen.next() {
+            total += (-(program.bits() as f64)).exp2();
+        }
+
+        assert!(total <= 1.0);
+        assert!(total > 0.5);
+    }
+
+    #[test]
+    fn ranking_order() {
+        let comp = Circuit::new(2);
+        crate::def::assert_ranking_order(&CircuitRanking::new(2), comp.generate(4));
+    }
+}
@@ -1,18 +1,105 @@
-use std::{fmt::Display, hash::Hash};
+use crate::adapters::{GeneratorIter, SkipPrograms, Sizes};
+use std::{fmt::Display, hash::Hash, ops::RangeInclusive};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum ProgResult<Output> {
     Out { output: Output, steps: usize },
     Error,
     Timeout,
+    // The program provably never halts.
+    NonHalting(Behavior),
 }
 
-pub trait Generator<Program> {
-    fn next(&mut self) -> Option<(Program, usize)>;
+// The behaviour of a program that never halts, as recognized by the decider that proved it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Behavior {
+    // Returns to the same configuration.
+    Cycler,
+    // Repeats the same computation shifted along the tape.
+    TranslatedCycler,
+    // Sweeps back and forth over a linearly growing part of the tape.
+    Bouncer,
+    // Increments a number written in some base.
+    Counter,
+    // Stays in a regular language of configurations that doesn't contain a halting one.
+    ClosedLanguage,
+    // The halting transitions or the end of a loop can't be reached.
+    Unreachable,
+}
+
+impl Display for Behavior {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Behavior::Cycler => "cycler",
+            Behavior::TranslatedCycler => "translated cycler",
+            Behavior::Bouncer => "bouncer",
+            Behavior::Counter => "counter",
+            Behavior::ClosedLanguage => "closed language",
+            Behavior::Unreachable => "unreachable halt",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Generates programs with their weights. By default the weight is the number of programs that
+// the generated program stands for.
+pub trait Generator<Program, Weight = usize> {
+    fn next(&mut self) -> Option<(Program, Weight)>;
+
+    // Raises the step limit of the executions of the next programs, for the generators that run
+    // the programs to decide which ones to return.
+    fn raise_max_steps(&mut self, _max_steps: usize) {}
+
+    // For the generators whose programs stand for programs of other sizes: keeps only the weight
+    // of the programs with the sizes in the range. Returns false if the programs only stand for
+    // programs of their own size, which `sizes` filters instead.
+    fn restrict_sizes(&mut self, _sizes: &RangeInclusive<usize>) -> bool {
+        false
+    }
+
+    fn iter(self) -> GeneratorIter<Self, Program, Weight>
+    where
+        Self: core::marker::Sized,
+    {
+        GeneratorIter::new(self)
+    }
+
+    // Only the programs with the sizes in the range, or that stand for programs with these sizes.
+    // Assumes that the programs are generated in the order of size, and stops at the first larger
+    // program.
+    fn sizes(self, range: RangeInclusive<usize>) -> Sizes<Self>
+    where
+        Self: core::marker::Sized,
+    {
+        Sizes::new(self, range)
+    }
+
+    // Skips the first n programs, e.g. to resume an interrupted run.
+    fn skip_programs(self, n: usize) -> SkipPrograms<Self>
+    where
+        Self: core::marker::Sized,
+    {
+        SkipPrograms::new(self, n)
+    }
+}
+
+// A generator that runs the programs to decide which ones to return, and returns them with their
+// results with the step limit set by `raise_max_steps`, so that they don't have to be run again.
+pub trait RunningGenerator<Program, Output, Weight = usize>: Generator<Program, Weight> {
+    fn next_run(&mut self) -> Option<(Program, ProgResult<Output>, Weight)>;
 }
 
 pub trait Sized {
     fn size(&self) -> usize;
+
+    // Description length in bits under the canonical encoding of the program family, so that
+    // programs of different systems can be compared in the same units.
+    fn bits(&self) -> usize;
+}
+
+// Length of the Elias gamma code of n >= 1.
+pub fn gamma_len(n: usize) -> usize {
+    2 * (usize::BITS - n.leading_zeros()) as usize - 1
 }
 
 pub trait System {
@@ -24,5 +111,88 @@ pub trait System {
 
     fn execute(&self, program: &Self::Program, max_steps: usize) -> ProgResult<Self::Output>;
 
+    // A slower decider, applied to the programs that time out as a second pass. Returns the
+    // behaviour of a program that never halts, with a certificate that can be checked
+    // independently.
+    fn decide(&self, _program: &Self::Program) -> Option<(Behavior, String)> {
+        None
+    }
+
     fn valid_output(o: &Self::Output) -> bool;
 }
+
+// A system whose executions can stop at the step limit and be resumed later with a larger one,
+// without running the first steps again.
+pub trait Resumable: System {
+    type Execution;
+
+    fn start(&self, program: &Self::Program) -> Self::Execution;
+
+    // Continues the execution until it stops or reaches `max_steps` steps in total. Returns None
+    // at the limit, and otherwise the same result as `execute` with the same limit.
+    fn resume(
+        &self,
+        program: &Self::Program,
+        execution: &mut Self::Execution,
+        max_steps: usize,
+    ) -> Option<ProgResult<Self::Output>>;
+
+    // For a generated program that stands for several programs with the given total weight:
+    // when its execution times out on a part that the program leaves undefined, returns the
+    // programs that define it, with their executions so far and their shares of the weight.
+    // Empty otherwise.
+    fn expand(
+        &self,
+        _program: &Self::Program,
+        _execution: &mut Self::Execution,
+        _weight: usize,
+    ) -> Vec<(Self::Program, Self::Execution, usize)> {
+        Vec::new()
+    }
+}
+
+// A bijection between the programs of a given size and the integers 0..count(size), following
+// the order of the naive enumeration.
+pub trait Ranking<Program> {
+    fn count(&self, size: usize) -> u128;
+
+    fn rank(&self, program: &Program) -> u128;
+
+    fn unrank(&self, size: usize, index: u128) -> Program;
+
+    // The largest size of the ranked programs.
+    fn max_size(&self) -> usize;
+
+    // Returns an error if a program, such as one given by the user, is not one of the ranked
+    // programs.
+    fn check(&self, program: &Program) -> Result<(), String>;
+
+    // Length of the self-delimiting code of a program of a given size: the Elias gamma code of
+    // size + 1, followed by the index of the program, arithmetically coded. The codes satisfy the
+    // Kraft inequality, so 2^-bits defines a (semi-)measure on programs.
+    fn code_bits(&self, size: usize) -> f64 {
+        gamma_len(size + 1) as f64 + (self.count(size) as f64).log2()
+    }
+}
+
+// Checks that a ranking follows the order of a generator, for the non-empty programs.
+#[cfg(test)]
+pub fn assert_ranking_order<P: Display + Sized>(
+    ranking: &impl Ranking<P>,
+    generator: impl Generator<P>,
+) {
+    let mut counts = Vec::new();
+    for (program, _) in generator.iter() {
+        let size = program.size();
+        if size >= counts.len() {
+            counts.resize(size + 1, 0);
+        }
+        let index = counts[size];
+        assert_eq!(ranking.rank(&program), index, "{}", program);
+        assert_eq!(ranking.unrank(size, index).to_string(), program.to_string());
+        counts[size] += 1;
+    }
+    for (size, &count) in counts.iter().enumerate().skip(1) {
+        assert_eq!(ranking.count(size), count, "size {}", size);
+    }
+}
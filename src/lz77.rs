@@ -0,0 +1,238 @@
+use crate::bit_seq::{self, BitCodeGenerator, BitCodeRanking, BitReader, BitSeq};
+use crate::def::{gamma_len, Generator, ProgResult, Sized, System};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lz77Token {
+    // Encoded as `0b`.
+    Literal(bool),
+    // Encoded as `1`, followed by Elias gamma codes of distance and length.
+    Copy { distance: usize, length: usize },
+}
+
+impl Lz77Token {
+    fn encoded_len(&self) -> usize {
+        match *self {
+            Lz77Token::Literal(_) => 2,
+            Lz77Token::Copy { distance, length } => 1 + gamma_len(distance) + gamma_len(length),
+        }
+    }
+}
+
+impl std::fmt::Display for Lz77Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Lz77Token::Literal(bit) => write!(f, "{}", bit as usize),
+            Lz77Token::Copy { distance, length } => write!(f, "({},{})", distance, length),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Lz77Program {
+    tokens: Vec<Lz77Token>,
+}
+
+impl Lz77Program {
+    // Returns None if the code ends in the middle of a token.
+    fn parse(reader: &mut BitReader) -> Option<Self> {
+        let mut tokens = Vec::new();
+        while reader.remaining() > 0 {
+            let token = if reader.read()? {
+                let distance = reader.read_gamma()?;
+                let length = reader.read_gamma()?;
+                Lz77Token::Copy { distance, length }
+            } else {
+                Lz77Token::Literal(reader.read()?)
+            };
+            tokens.push(token);
+        }
+        Some(Lz77Program { tokens })
+    }
+
+    fn encode(&self) -> BitSeq {
+        let mut code = BitSeq::new();
+        for &token in self.tokens.iter() {
+            match token {
+                Lz77Token::Literal(bit) => {
+                    code.push(false);
+                    code.push(bit);
+                }
+                Lz77Token::Copy { distance, length } => {
+                    code.push(true);
+                    code.push_gamma(distance);
+                    code.push_gamma(length);
+                }
+            }
+        }
+        code
+    }
+
+    // The number of codes of `len` bits that start with `prefix`: the ways to end the token that
+    // the prefix stops in, followed by any tokens.
+    fn completions(prefix: &[bool], len: usize) -> u128 {
+        let Some(remaining) = len.checked_sub(prefix.len()) else {
+            return 0;
+        };
+        let gammas = bit_seq::gamma_ends(0, remaining);
+        // The numbers of tokens and of token sequences by length.
+        let mut tokens = vec![0; remaining + 1];
+        let copies = bit_seq::convolve(&gammas, &gammas);
+        tokens[1..].copy_from_slice(&copies[..remaining]);
+        if remaining >= 2 {
+            tokens[2] += 2;
+        }
+        let mut sequences = vec![1; remaining + 1];
+        for n in 1..=remaining {
+            sequences[n] = (1..=n).map(|k| tokens[k] * sequences[n - k]).sum();
+        }
+
+        let mut i = 0;
+        let end = loop {
+            if i == prefix.len() {
+                break vec![1];
+            }
+            if !prefix[i] {
+                if i + 1 == prefix.len() {
+                    break vec![0, 2];
+                }
+                i += 2;
+                continue;
+            }
+            i += 1;
+            if let Some(distance) = bit_seq::gamma_end(prefix, &mut i, remaining) {
+                break bit_seq::convolve(&distance, &gammas);
+            }
+            if let Some(length) = bit_seq::gamma_end(prefix, &mut i, remaining) {
+                break length;
+            }
+        };
+        bit_seq::convolve(&sequences, &end)[remaining]
+    }
+}
+
+impl Sized for Lz77Program {
+    // The size of the program is the number of bits in its encoding.
+    fn size(&self) -> usize {
+        self.tokens.iter().map(|t| t.encoded_len()).sum()
+    }
+
+    fn bits(&self) -> usize {
+        self.size()
+    }
+}
+
+impl std::fmt::Display for Lz77Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for token in self.tokens.iter() {
+            write!(f, "{}", token)?;
+        }
+        std::fmt::Result::Ok(())
+    }
+}
+
+pub struct Lz77 {}
+
+impl Lz77 {
+    pub fn new() -> Self {
+        Lz77 {}
+    }
+
+    // Ranks the programs in the order of `generate`.
+    pub fn ranking(&self) -> BitCodeRanking<Lz77Program> {
+        BitCodeRanking::new(Lz77Program::parse, Lz77Program::encode, Lz77Program::completions)
+    }
+}
+
+impl System for Lz77 {
+    type Output = BitSeq;
+    type Program = Lz77Program;
+
+    fn generate(&self, limit: usize) -> impl Generator<Lz77Program> {
+        // Since the token code is prefix-free, every bit string corresponds to at most one
+        // token sequence.
+        BitCodeGenerator::new(limit, Lz77Program::parse)
+    }
+
+    // Each output bit is counted as a step.
+    fn execute(&self, program: &Lz77Program, max_steps: usize) -> ProgResult<BitSeq> {
+        let mut output = BitSeq::new();
+
+        for &token in program.tokens.iter() {
+            match token {
+                Lz77Token::Literal(bit) => output.push(bit),
+                Lz77Token::Copy { distance, length } => {
+                    if distance > output.len() {
+                        return ProgResult::Error;
+                    }
+                    for _ in 0..length {
+                        let bit = output.0[output.len() - distance];
+                        output.push(bit);
+                    }
+                }
+            }
+            if output.len() >= max_steps {
+                return ProgResult::Timeout;
+            }
+        }
+
+        let steps = output.len();
+        ProgResult::Out { output, steps }
+    }
+
+    fn valid_output(o: &BitSeq) -> bool {
+        !o.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overlapping_copy() {
+        let program = Lz77Program {
+            tokens: vec![
+                Lz77Token::Literal(true),
+                Lz77Token::Literal(false),
+                Lz77Token::Copy { distance: 2, length: 5 },
+            ],
+        };
+        // 2 + 2 + (1 + 3 + 5)
+        assert_eq!(program.size(), 13);
+        match Lz77::new().execute(&program, 1000) {
+            ProgResult::Out { output, steps } => {
+                assert_eq!(output.to_string(), "1010101");
+                assert_eq!(steps, 7);
+            }
+            _ => panic!("expected output"),
+        }
+    }
+
+    #[test]
+    fn generator_sizes() {
+        let comp = Lz77::new();
+        let mut gen = comp.generate(10);
+        let mut last_size = 0;
+        let mut count = 0;
+        while let Some((program, _)) = gen.next() {
+            assert!(program.size() >= last_size);
+            last_size = program.size();
+            count += 1;
+        }
+        assert_eq!(last_size, 10);
+        // Only literals fit into 2 bits: 00, 01.
+        let mut gen = comp.generate(2);
+        let mut small = 0;
+        while gen.next().is_some() {
+            small += 1;
+        }
+        assert_eq!(small, 2);
+        assert!(count > small);
+    }
+
+    #[test]
+    fn ranking_order() {
+        let comp = Lz77::new();
+        crate::def::assert_ranking_order(&comp.ranking(), comp.generate(14));
+    }
+}
@@ -1,19 +1,105 @@
+mod adapters;
 mod bf_count;
 mod bf_num0;
+mod bit_seq;
+mod bit_tape;
 mod brainfuck;
+mod circuit;
 mod def;
+mod lz77;
+mod markov;
+mod prior;
+mod sample;
 mod stat;
+mod transducer;
 mod turing_count;
+mod turing_deciders;
+mod turing_macro;
 
-use crate::def::{System, Generator, ProgResult};
+use crate::bf_count::BfCount;
+use crate::bf_num0::BfNum0;
+use crate::bit_seq::BitSeq;
+use crate::brainfuck::{BfIncremental, BfNaiveGenerator, BfPrefixSearch, BfRanking};
+use crate::adapters::Executed;
+use crate::def::{System, Generator, ProgResult, Ranking, Resumable, RunningGenerator};
+use crate::turing_count::{TuringCount, TuringCountRanking};
+use std::io::Write;
 
-fn run<CS: System>(comp: &CS, max_size: usize) {
-    let mut gen = comp.generate(max_size);
-    let mut stat: stat::Stat<CS> = stat::Stat::new();
+// The prefix search needs a fixed step limit, since it doesn't go through the programs in the
+// order of size.
+const PREFIX_SEARCH_MAX_STEPS: usize = 1000;
+
+// The step limits of the holdout pipeline, raised in turn for the programs that time out.
+const HOLDOUT_LIMITS: [usize; 4] = [1000, 10_000, 100_000, 1_000_000];
+
+// The file that the certificates of the programs decided after a timeout are written to.
+type Certificates = Option<std::io::BufWriter<std::fs::File>>;
+
+fn create_certificates(path: &str) -> Certificates {
+    let file = std::fs::File::create(path).unwrap_or_else(|e| {
+        eprintln!("Can't create {}: {}", path, e);
+        std::process::exit(1);
+    });
+    Some(std::io::BufWriter::new(file))
+}
+
+// Runs the programs up to the given size, from a generator that runs them. Options:
+//   --min-size N  skips the programs smaller than N (a smaller canonical program is still run
+//                 for the programs of size N and more that reduce to it)
+//   --skip N      skips the first N programs, to resume an interrupted run
+//   --certificates FILE
+//                 writes the non-halting certificates of the programs that time out to FILE
+fn run<CS: System>(
+    comp: &CS,
+    gen: impl RunningGenerator<CS::Program, CS::Output>,
+    max_size: usize,
+    options: &[String],
+) {
+    let mut min_size = 0;
+    let mut skip = 0;
+    let mut certificates = None;
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name == "--min-size" => {
+                min_size = value.parse().expect("--min-size should be a number");
+            }
+            [name, value] if name == "--skip" => {
+                skip = value.parse().expect("--skip should be a number");
+            }
+            [name, value] if name == "--certificates" => {
+                certificates = create_certificates(value);
+            }
+            _ => {
+                eprintln!("Unknown option: {}", option.join(" "));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let gen = gen.sizes(min_size..=max_size).skip_programs(skip);
+    run_generator(comp, gen, stat::Stat::new(), certificates);
+}
+
+// Runs the programs up to the given size from the generator of the system.
+fn run_system<CS: System>(comp: &CS, max_size: usize, options: &[String]) {
+    run(comp, Executed::new(comp, comp.generate(max_size)), max_size, options);
+}
+
+fn run_generator<CS: System, W: stat::Weight>(
+    comp: &CS,
+    mut gen: impl RunningGenerator<CS::Program, CS::Output, W>,
+    mut stat: stat::Stat<CS, W>,
+    mut certificates: Certificates,
+) {
     let mut max_steps = 0;
 
-    while let Some((program, weight)) = gen.next() {
-        let result = comp.execute(&program, std::cmp::max(1000, 4 * max_steps));
+    loop {
+        let limit = std::cmp::max(1000, 4 * max_steps);
+        gen.raise_max_steps(limit);
+        let Some((program, result, weight)) = gen.next_run() else {
+            break;
+        };
+        let result = decide_timeout(comp, &program, result, &mut certificates);
         let new = stat.register(&program, &result, weight);
         if let ProgResult::Out { output, steps } = result {
             if steps > max_steps {
@@ -30,8 +116,384 @@ fn run<CS: System>(comp: &CS, max_size: usize) {
     stat.print();
 }
 
+// Applies the second pass to a program that times out.
+fn decide_timeout<CS: System>(
+    comp: &CS,
+    program: &CS::Program,
+    result: ProgResult<CS::Output>,
+    certificates: &mut Certificates,
+) -> ProgResult<CS::Output> {
+    if let ProgResult::Timeout = result {
+        if let Some((behavior, certificate)) = comp.decide(program) {
+            if let Some(file) = certificates {
+                writeln!(file, "{}  {}  {}", program, behavior, certificate)
+                    .expect("Failed to write the certificates");
+            }
+            return ProgResult::NonHalting(behavior);
+        }
+    }
+    result
+}
+
+// Runs the programs up to the given size with a fixed step limit, so that the results don't
+// depend on the order of the programs. The executions that reach the limit are held out, and
+// resumed where they stopped with each larger limit in turn. The outputs that only appear after
+// a raise are printed with the limit. Options:
+//   --limits N,N,...  the strictly increasing step limits
+//   --certificates FILE
+//                     writes the non-halting certificates of the programs that time out to FILE
+fn run_holdout<CS: Resumable>(comp: &CS, max_size: usize, options: &[String]) {
+    let mut limits = HOLDOUT_LIMITS.to_vec();
+    let mut certificates = None;
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name == "--limits" => {
+                limits = parse_limits(value).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            }
+            [name, value] if name == "--certificates" => {
+                certificates = create_certificates(value);
+            }
+            _ => {
+                eprintln!("Unknown option: {}", option.join(" "));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut stat: stat::Stat<CS> = stat::Stat::new();
+    let mut register = |program: &CS::Program, result, weight: usize, limit: Option<usize>| {
+        let result = decide_timeout(comp, program, result, &mut certificates);
+        if stat.register(program, &result, weight) {
+            if let ProgResult::Out { output, steps: _ } = result {
+                match limit {
+                    Some(limit) => println!("{}  {}  (step limit {})", output, program, limit),
+                    None => println!("{}  {}", output, program),
+                }
+            }
+        }
+    };
+
+    // Resumes the executions with a limit. The programs that time out on a part that they leave
+    // undefined are expanded, and their expansions are resumed in their place. Returns the
+    // executions that reach the limit, in order.
+    let mut resume_all = |mut pending: Vec<(CS::Program, usize, CS::Execution)>,
+                          limit: usize,
+                          raised: Option<usize>| {
+        pending.reverse();
+        let mut holdouts = Vec::new();
+        while let Some((program, weight, mut execution)) = pending.pop() {
+            match comp.resume(&program, &mut execution, limit) {
+                Some(ProgResult::Timeout) => {
+                    let children = comp.expand(&program, &mut execution, weight);
+                    if children.is_empty() {
+                        register(&program, ProgResult::Timeout, weight, raised);
+                    }
+                    for (child, execution, weight) in children.into_iter().rev() {
+                        pending.push((child, weight, execution));
+                    }
+                }
+                Some(result) => register(&program, result, weight, raised),
+                None => holdouts.push((program, weight, execution)),
+            }
+        }
+        holdouts
+    };
+
+    let mut holdouts = Vec::new();
+    let mut gen = comp.generate(max_size);
+    gen.raise_max_steps(limits[0]);
+    for (program, weight) in gen.iter() {
+        let execution = comp.start(&program);
+        holdouts.extend(resume_all(vec![(program, weight, execution)], limits[0], None));
+    }
+
+    for &limit in &limits[1..] {
+        println!("Step limit: {}, held out: {}", limit, holdouts.len());
+        holdouts = resume_all(holdouts, limit, Some(limit));
+    }
+    for (program, weight, _) in holdouts {
+        register(&program, ProgResult::Timeout, weight, None);
+    }
+
+    stat.print();
+}
+
+// Parses the step limits of the holdout pipeline, which should be strictly increasing.
+fn parse_limits(value: &str) -> Result<Vec<usize>, String> {
+    let limits = value
+        .split(',')
+        .map(|limit| limit.parse().map_err(|_| "--limits should be numbers".to_string()))
+        .collect::<Result<Vec<usize>, _>>()?;
+    if !limits.windows(2).all(|w| w[0] < w[1]) {
+        return Err("--limits should be strictly increasing".to_string());
+    }
+    Ok(limits)
+}
+
+fn show<CS: System>(comp: &CS, program: &CS::Program, index: u128) {
+    print!("{}  {}  ", index, program);
+    match comp.execute(program, 1_000_000) {
+        ProgResult::Out { output, steps } => println!("{} in {} steps", output, steps),
+        ProgResult::Error => println!("error"),
+        ProgResult::Timeout => match comp.decide(program) {
+            Some((behavior, certificate)) => {
+                println!("non-halting ({})  {}", behavior, certificate)
+            }
+            None => println!("timeout"),
+        },
+        ProgResult::NonHalting(behavior) => println!("non-halting ({})", behavior),
+    }
+}
+
+// Prints the program with a given index among the programs of a given size, or the index of
+// a given program.
+fn lookup<CS, R>(comp: &CS, ranking: &R, args: &[String])
+where
+    CS: System,
+    CS::Program: std::str::FromStr<Err = String>,
+    R: Ranking<CS::Program>,
+{
+    match args {
+        [size, index] => {
+            let size: usize = size.parse().expect("SIZE should be a number");
+            let index: u128 = index.parse().expect("INDEX should be a number");
+            if size > ranking.max_size() {
+                eprintln!("The programs have at most size {}", ranking.max_size());
+                std::process::exit(1);
+            }
+            if index >= ranking.count(size) {
+                eprintln!("There are only {} programs of size {}", ranking.count(size), size);
+                std::process::exit(1);
+            }
+            show(comp, &ranking.unrank(size, index), index);
+        }
+        [program] => {
+            let program: CS::Program = program.parse().unwrap_or_else(|e: String| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            if let Err(e) = ranking.check(&program) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            show(comp, &program, ranking.rank(&program));
+        }
+        _ => {
+            eprintln!("Usage: kolmogorov lookup SYSTEM (SIZE INDEX | PROGRAM)");
+            std::process::exit(1);
+        }
+    }
+}
+
+// Runs a random sample of programs of a given size. With a range of sizes MIN..MAX, takes the
+// same number of samples of each size in turn.
+fn sample<CS, R>(comp: &CS, ranking: R, args: &[String])
+where
+    CS: System,
+    R: Ranking<CS::Program> + Clone,
+{
+    let [size, samples, seed @ ..] = args else {
+        eprintln!("Usage: kolmogorov sample SYSTEM (SIZE | MIN..MAX) SAMPLES [SEED]");
+        std::process::exit(1);
+    };
+    let parse_size = |s: &str| s.parse::<usize>().expect("SIZE should be a number");
+    let sizes = match size.split_once("..") {
+        Some((min, max)) => parse_size(min)..=parse_size(max),
+        None => parse_size(size)..=parse_size(size),
+    };
+    if *sizes.end() > ranking.max_size() {
+        eprintln!("The programs have at most size {}", ranking.max_size());
+        std::process::exit(1);
+    }
+    let samples = samples.parse().expect("SAMPLES should be a number");
+    let seed: u64 = seed.first().map_or(0, |s| s.parse().expect("SEED should be a number"));
+    // The first size uses the seed itself, so that the samples of a single size are the same
+    // with or without a range. The other sizes take their seeds from the SplitMix stream of the
+    // seed, so that they don't share streams with the runs with nearby seeds.
+    let mut seeds = sample::Rng::new(seed);
+    let first = *sizes.start();
+    let gens: Vec<_> = sizes
+        .map(|size| (size, if size == first { seed } else { seeds.next_u64() }))
+        // Some sizes have no programs, such as the LZ77 codes of 1 bit.
+        .filter(|&(size, _)| ranking.count(size) > 0)
+        .map(|(size, seed)| sample::RandomGenerator::new(ranking.clone(), size, samples, seed))
+        .collect();
+    if gens.is_empty() {
+        eprintln!("There are no programs of size {}", size);
+        std::process::exit(1);
+    }
+    let gen = Executed::new(comp, adapters::Interleave::new(gens));
+    run_generator(comp, gen, stat::Stat::new_sampled(), None);
+}
+
+// Weighs the programs by the universal prior, 2^-(length of the self-delimiting code).
+fn run_prior<CS: System, R: Ranking<CS::Program>>(
+    comp: &CS,
+    gen: impl Generator<CS::Program>,
+    ranking: R,
+) {
+    let gen = Executed::new(comp, prior::PriorGenerator::new(gen, ranking));
+    run_generator(comp, gen, stat::Stat::new(), None);
+}
+
+// Runs all the programs up to the given size, sharing the execution of common prefixes.
+fn run_prefix_search<CS: BfIncremental>(comp: &CS, max_size: usize) {
+    let mut search = BfPrefixSearch::new(comp, max_size, PREFIX_SEARCH_MAX_STEPS);
+    let mut stat: stat::Stat<CS> = stat::Stat::new();
+
+    while let Some((program, result, weight)) = search.next() {
+        if stat.register(&program, &result, weight) {
+            if let ProgResult::Out { output, steps: _ } = result {
+                println!("{}  {}", output, program);
+            }
+        }
+    }
+
+    stat.print();
+}
+
+fn unsupported(command: &str, system: &str) -> ! {
+    eprintln!("{} is not supported for system: {}", command, system);
+    std::process::exit(1);
+}
+
+// Usage:
+//   kolmogorov [SYSTEM [MAX_SIZE [--min-size N] [--skip N] [--certificates FILE]]]
+//   kolmogorov lookup SYSTEM (SIZE INDEX | PROGRAM)
+//   kolmogorov sample SYSTEM (SIZE | MIN..MAX) SAMPLES [SEED]
+//   kolmogorov prior SYSTEM MAX_SIZE
+//   kolmogorov prefix SYSTEM MAX_SIZE
+//   kolmogorov holdout SYSTEM MAX_SIZE [--limits N,N,...] [--certificates FILE]
 fn main() {
-    // run(&bf_count::BfCount::new(), 11);
-    // run(&bf_num0::BfNum0::new(), 11);
-    run(&turing_count::TuringCount::new(), 4);
+    let args: Vec<String> = std::env::args().collect();
+    let arg = |i: usize| args.get(i).map(|s| s.as_str());
+    let rest = args.get(3..).unwrap_or(&[]);
+
+    match (arg(1), arg(2)) {
+        (Some("sample"), Some(system)) => match system {
+            "bf_count" => sample(&BfCount::new(), BfRanking::new(false, true), rest),
+            "bf_num0" => sample(&BfNum0::new(), BfRanking::new(true, false), rest),
+            "turing_count" => sample(&TuringCount::new(), TuringCountRanking::new(), rest),
+            "circuit" => sample(&circuit::Circuit::new(3), circuit::CircuitRanking::new(3), rest),
+            "transducer" => {
+                let comp = transducer::Transducer::new();
+                sample(&comp, comp.ranking(), rest)
+            }
+            "markov" => {
+                sample(&markov::Markov::new(BitSeq::new()), markov::MarkovRanking::new(), rest)
+            }
+            "lz77" => {
+                let comp = lz77::Lz77::new();
+                sample(&comp, comp.ranking(), rest)
+            }
+            _ => unsupported("Sampling", system),
+        },
+        (Some("lookup"), Some(system)) => match system {
+            "bf_count" => lookup(&BfCount::new(), &BfRanking::new(false, true), rest),
+            "bf_num0" => lookup(&BfNum0::new(), &BfRanking::new(true, false), rest),
+            "turing_count" => lookup(&TuringCount::new(), &TuringCountRanking::new(), rest),
+            _ => unsupported("Lookup", system),
+        },
+        (Some("prior"), Some(system)) => {
+            let max_size = rest.first().map(|s| s.parse().expect("MAX_SIZE should be a number"));
+            match system {
+                // The canonical generator can't be used, since its programs stand for the
+                // programs of different sizes.
+                "bf_count" => run_prior(
+                    &BfCount::new(),
+                    BfNaiveGenerator::new(max_size.unwrap_or(11), false, true),
+                    BfRanking::new(false, true),
+                ),
+                "bf_num0" => run_prior(
+                    &BfNum0::new(),
+                    BfNaiveGenerator::new(max_size.unwrap_or(11), true, false),
+                    BfRanking::new(true, false),
+                ),
+                "turing_count" => {
+                    let comp = TuringCount::new();
+                    let gen = comp.generate(max_size.unwrap_or(4));
+                    run_prior(&comp, gen, TuringCountRanking::new())
+                }
+                "circuit" => {
+                    let comp = circuit::Circuit::new(3);
+                    let gen = comp.generate(max_size.unwrap_or(5));
+                    run_prior(&comp, gen, circuit::CircuitRanking::new(3))
+                }
+                "transducer" => {
+                    let comp = transducer::Transducer::new();
+                    run_prior(&comp, comp.generate(max_size.unwrap_or(24)), comp.ranking())
+                }
+                "markov" => {
+                    let comp = markov::Markov::new(BitSeq::new());
+                    let gen = comp.generate(max_size.unwrap_or(8));
+                    run_prior(&comp, gen, markov::MarkovRanking::new())
+                }
+                "lz77" => {
+                    let comp = lz77::Lz77::new();
+                    run_prior(&comp, comp.generate(max_size.unwrap_or(24)), comp.ranking())
+                }
+                _ => unsupported("Universal prior", system),
+            }
+        }
+        (Some("prefix"), Some(system)) => {
+            let max_size = rest.first().map(|s| s.parse().expect("MAX_SIZE should be a number"));
+            match system {
+                "bf_count" => run_prefix_search(&BfCount::new(), max_size.unwrap_or(11)),
+                "bf_num0" => run_prefix_search(&BfNum0::new(), max_size.unwrap_or(11)),
+                _ => unsupported("Prefix search", system),
+            }
+        }
+        (Some("holdout"), Some(system)) => {
+            let max_size = rest.first().map(|s| s.parse().expect("MAX_SIZE should be a number"));
+            let options = rest.get(1..).unwrap_or(&[]);
+            match system {
+                "turing_count" => run_holdout(&TuringCount::new(), max_size.unwrap_or(4), options),
+                _ => unsupported("Holdout", system),
+            }
+        }
+        (system, max_size) => {
+            let system = system.unwrap_or("turing_count");
+            let max_size = max_size.map(|s| s.parse().expect("MAX_SIZE should be a number"));
+            match system {
+                "bf_count" => run_system(&BfCount::new(), max_size.unwrap_or(11), rest),
+                "bf_num0" => run_system(&BfNum0::new(), max_size.unwrap_or(11), rest),
+                "turing_count" => {
+                    // The tree generator runs the machines already.
+                    let comp = TuringCount::new();
+                    let max_size = max_size.unwrap_or(4);
+                    run(&comp, comp.generate_runs(max_size), max_size, rest)
+                }
+                "circuit" => run_system(&circuit::Circuit::new(3), max_size.unwrap_or(5), rest),
+                "transducer" => {
+                    run_system(&transducer::Transducer::new(), max_size.unwrap_or(24), rest)
+                }
+                "markov" => {
+                    run_system(&markov::Markov::new(BitSeq::new()), max_size.unwrap_or(8), rest)
+                }
+                "lz77" => run_system(&lz77::Lz77::new(), max_size.unwrap_or(24), rest),
+                _ => {
+                    eprintln!("Unknown system: {}", system);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+#[test]
+fn holdout_limits() {
+    assert_eq!(parse_limits("1000"), Ok(vec![1000]));
+    assert_eq!(parse_limits("10,100,1000"), Ok(vec![10, 100, 1000]));
+    assert!(parse_limits("10,100,100").is_err());
+    assert!(parse_limits("100,10").is_err());
+    assert!(parse_limits("").is_err());
+    assert!(parse_limits("10,x").is_err());
+}
 }
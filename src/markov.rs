@@ -0,0 +1,384 @@
+use crate::bit_seq::BitSeq;
+use crate::def::{Generator, ProgResult, Ranking, Sized, System};
+
+#[derive(Clone, Debug)]
+struct MarkovRule {
+    pattern: BitSeq,
+    replacement: BitSeq,
+    terminating: bool,
+}
+
+impl MarkovRule {
+    fn size(&self) -> usize {
+        self.pattern.len() + self.replacement.len() + 1
+    }
+}
+
+impl std::fmt::Display for MarkovRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}->", self.pattern)?;
+        if self.terminating {
+            write!(f, ".")?;
+        }
+        write!(f, "{}", self.replacement)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MarkovProgram {
+    rules: Vec<MarkovRule>,
+}
+
+impl Sized for MarkovProgram {
+    // Total length of the rules, with an extra symbol per rule for the arrow.
+    fn size(&self) -> usize {
+        self.rules.iter().map(|r| r.size()).sum()
+    }
+
+    // Each rule is encoded as `1`, the pattern and the replacement with every bit b as `1b` and
+    // ended by `0`, and the terminating flag. The list of rules is ended by `0`.
+    fn bits(&self) -> usize {
+        let rules: usize = self
+            .rules
+            .iter()
+            .map(|r| 4 + 2 * (r.pattern.len() + r.replacement.len()))
+            .sum();
+        rules + 1
+    }
+}
+
+impl std::fmt::Display for MarkovProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", rule)?;
+        }
+        std::fmt::Result::Ok(())
+    }
+}
+
+// Enumerates the programs by size. For every size, iterates over the compositions of the size
+// into rule sizes, and for each composition over all the rules of the given sizes.
+pub struct MarkovGenerator {
+    max_size: usize,
+    size: usize,
+    composition: usize,
+    index_within_composition: usize,
+    total_for_composition: usize,
+}
+
+impl MarkovGenerator {
+    fn new(max_size: usize) -> Self {
+        MarkovGenerator {
+            max_size,
+            size: 0,
+            composition: 0,
+            index_within_composition: 0,
+            total_for_composition: 0,
+        }
+    }
+
+    // The bits of `composition` mark the places where the size is split between the rules.
+    fn rule_sizes(&self) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        let mut current = 1;
+        for i in 0..self.size - 1 {
+            if (self.composition >> i) & 1 != 0 {
+                sizes.push(current);
+                current = 1;
+            } else {
+                current += 1;
+            }
+        }
+        sizes.push(current);
+        sizes
+    }
+
+    fn next_composition(&mut self) {
+        self.composition += 1;
+        if self.size == 0 || self.composition >= 1 << (self.size - 1) {
+            self.size += 1;
+            self.composition = 0;
+        }
+        self.index_within_composition = 0;
+        // A rule of size m has m - 1 symbols, which could be split between the pattern and
+        // the replacement in m ways, and can be terminating or not.
+        self.total_for_composition = self
+            .rule_sizes()
+            .iter()
+            .map(|&m| m << m)
+            .product();
+    }
+
+    fn rule_from_idx(size: usize, idx: &mut usize) -> MarkovRule {
+        let terminating = !(*idx).is_multiple_of(2);
+        *idx /= 2;
+        let pattern_len = *idx % size;
+        *idx /= size;
+        let symbols = *idx % (1 << (size - 1));
+        *idx >>= size - 1;
+
+        let bit = |i: usize| (symbols >> i) & 1 != 0;
+        MarkovRule {
+            pattern: BitSeq((0..pattern_len).map(bit).collect()),
+            replacement: BitSeq((pattern_len..size - 1).map(bit).collect()),
+            terminating,
+        }
+    }
+}
+
+impl Generator<MarkovProgram> for MarkovGenerator {
+    fn next(&mut self) -> Option<(MarkovProgram, usize)> {
+        if self.index_within_composition >= self.total_for_composition {
+            self.next_composition();
+        }
+        if self.size > self.max_size {
+            return None;
+        }
+
+        let mut idx = self.index_within_composition;
+        self.index_within_composition += 1;
+
+        let rules = self
+            .rule_sizes()
+            .into_iter()
+            .map(|m| Self::rule_from_idx(m, &mut idx))
+            .collect();
+
+        Some((MarkovProgram { rules }, 1))
+    }
+}
+
+// Ranks the programs in the order of `MarkovGenerator`: by the composition of the size into rule
+// sizes, and within a composition with the rules as the digits of the index, the first rule as
+// the lowest one.
+#[derive(Clone)]
+pub struct MarkovRanking {
+    // The number of programs by size, as long as it fits.
+    counts: Vec<u128>,
+}
+
+impl MarkovRanking {
+    pub fn new() -> Self {
+        let mut counts = vec![1];
+        'sizes: loop {
+            let size = counts.len();
+            let mut count: u128 = 0;
+            for m in 1..=size {
+                let rules = Self::rules(m).and_then(|rules| rules.checked_mul(counts[size - m]));
+                match rules.and_then(|rules| count.checked_add(rules)) {
+                    Some(sum) => count = sum,
+                    None => break 'sizes,
+                }
+            }
+            counts.push(count);
+        }
+        MarkovRanking { counts }
+    }
+
+    // The number of rules of a given size, see `MarkovGenerator::next_composition`.
+    fn rules(size: usize) -> Option<u128> {
+        (size as u128).checked_mul(1u128.checked_shl(size as u32)?)
+    }
+
+    // The number of programs with `len` symbols whose last rule has `tail` more symbols.
+    fn merged(&self, len: usize, tail: usize) -> u128 {
+        (1..=len).map(|m| Self::rules(m + tail).unwrap() * self.counts[len - m]).sum()
+    }
+
+    fn rule_idx(rule: &MarkovRule) -> u128 {
+        let size = rule.size();
+        let symbols = rule.pattern.0.iter().chain(rule.replacement.0.iter());
+        let symbols = symbols.rev().fold(0, |symbols, &bit| (symbols << 1) | bit as u128);
+        rule.terminating as u128 + 2 * (rule.pattern.len() as u128 + size as u128 * symbols)
+    }
+}
+
+impl Ranking<MarkovProgram> for MarkovRanking {
+    fn count(&self, size: usize) -> u128 {
+        self.counts[size]
+    }
+
+    // A composition comes after those that have the same splits after some place, where it
+    // splits and they don't.
+    fn rank(&self, program: &MarkovProgram) -> u128 {
+        let mut index = 0;
+        let mut split = program.size();
+        let mut above = 1;
+        for pair in program.rules.windows(2).rev() {
+            let tail = pair[1].size();
+            split -= tail;
+            index += above * self.merged(split, tail);
+            above *= Self::rules(tail).unwrap();
+        }
+
+        let within = program.rules.iter().rev().fold(0, |within, rule| {
+            within * Self::rules(rule.size()).unwrap() + Self::rule_idx(rule)
+        });
+        index + within
+    }
+
+    fn unrank(&self, size: usize, mut index: u128) -> MarkovProgram {
+        assert!(index < self.count(size));
+        if size == 0 {
+            return MarkovProgram { rules: Vec::new() };
+        }
+        // The rule sizes, from the last rule.
+        let mut sizes = Vec::new();
+        let mut above = 1;
+        let mut tail = 1;
+        for split in (1..size).rev() {
+            let merged = above * self.merged(split, tail);
+            if index < merged {
+                tail += 1;
+            } else {
+                index -= merged;
+                sizes.push(tail);
+                above *= Self::rules(tail).unwrap();
+                tail = 1;
+            }
+        }
+        sizes.push(tail);
+
+        let rules = sizes
+            .into_iter()
+            .rev()
+            .map(|m| {
+                let rules = Self::rules(m).unwrap();
+                let mut idx = (index % rules) as usize;
+                index /= rules;
+                MarkovGenerator::rule_from_idx(m, &mut idx)
+            })
+            .collect();
+        MarkovProgram { rules }
+    }
+
+    fn max_size(&self) -> usize {
+        self.counts.len() - 1
+    }
+
+    fn check(&self, program: &MarkovProgram) -> Result<(), String> {
+        if program.size() <= self.max_size() {
+            Ok(())
+        } else {
+            Err(format!("The programs have at most size {}", self.max_size()))
+        }
+    }
+}
+
+// A Markov algorithm over {0, 1}. On each step, the first rule whose pattern occurs in the word
+// replaces the leftmost occurrence of the pattern. The algorithm stops when no rule applies or
+// after applying a terminating rule. The output is the final word.
+pub struct Markov {
+    start: BitSeq,
+}
+
+impl Markov {
+    pub fn new(start: BitSeq) -> Self {
+        Markov { start }
+    }
+
+    fn find(word: &[bool], pattern: &[bool]) -> Option<usize> {
+        (0..=word.len().checked_sub(pattern.len())?)
+            .find(|&i| word[i..i + pattern.len()] == *pattern)
+    }
+}
+
+impl System for Markov {
+    type Output = BitSeq;
+    type Program = MarkovProgram;
+
+    fn generate(&self, limit: usize) -> impl Generator<MarkovProgram> {
+        MarkovGenerator::new(limit)
+    }
+
+    fn execute(&self, program: &MarkovProgram, max_steps: usize) -> ProgResult<BitSeq> {
+        let mut word = self.start.0.clone();
+        let mut step = 0;
+
+        loop {
+            let found = program.rules.iter().find_map(|rule| {
+                Self::find(&word, &rule.pattern.0).map(|pos| (rule, pos))
+            });
+            let Some((rule, pos)) = found else {
+                break;
+            };
+            if step >= max_steps {
+                return ProgResult::Timeout;
+            }
+            step += 1;
+
+            word.splice(
+                pos..pos + rule.pattern.len(),
+                rule.replacement.0.iter().cloned(),
+            );
+
+            if rule.terminating {
+                break;
+            }
+        }
+
+        ProgResult::Out {
+            output: BitSeq(word),
+            steps: step,
+        }
+    }
+
+    fn valid_output(o: &BitSeq) -> bool {
+        !o.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(pattern: &[bool], replacement: &[bool], terminating: bool) -> MarkovRule {
+        MarkovRule {
+            pattern: BitSeq(pattern.to_vec()),
+            replacement: BitSeq(replacement.to_vec()),
+            terminating,
+        }
+    }
+
+    #[test]
+    fn execute() {
+        let comp = Markov::new(BitSeq::new());
+
+        let program = MarkovProgram {
+            rules: vec![rule(&[true], &[false], true), rule(&[], &[true], false)],
+        };
+        assert_eq!(program.size(), 5);
+        assert_eq!(program.bits(), 15);
+        match comp.execute(&program, 100) {
+            ProgResult::Out { output, steps } => {
+                assert_eq!(output.to_string(), "0");
+                assert_eq!(steps, 2);
+            }
+            _ => panic!("expected output"),
+        }
+
+        let program = MarkovProgram {
+            rules: vec![rule(&[], &[true], false)],
+        };
+        assert!(matches!(comp.execute(&program, 100), ProgResult::Timeout));
+    }
+
+    #[test]
+    fn generator_counts() {
+        let mut gen = MarkovGenerator::new(3);
+        let mut runs = [0; 4];
+        while let Some((program, _)) = gen.next() {
+            runs[program.size()] += 1;
+        }
+        // A rule of size m can be chosen in m * 2^m ways.
+        assert_eq!(runs, [0, 2, 8 + 2 * 2, 24 + 2 * 8 + 8 * 2 + 2 * 2 * 2]);
+    }
+
+    #[test]
+    fn ranking_order() {
+        crate::def::assert_ranking_order(&MarkovRanking::new(), MarkovGenerator::new(6));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::def::{Generator, Ranking, Sized};
+use std::marker::PhantomData;
+
+// Converts the weights of another generator to the universal prior: each program has the weight
+// 2^-bits, where bits is the length of its self-delimiting code. The programs that are
+// represented by a generated program should all have the same size.
+pub struct PriorGenerator<P, G: Generator<P>, R: Ranking<P>> {
+    generator: G,
+    ranking: R,
+    program: PhantomData<P>,
+}
+
+impl<P, G: Generator<P>, R: Ranking<P>> PriorGenerator<P, G, R> {
+    pub fn new(generator: G, ranking: R) -> Self {
+        PriorGenerator {
+            generator,
+            ranking,
+            program: PhantomData,
+        }
+    }
+}
+
+impl<P: Sized, G: Generator<P>, R: Ranking<P>> Generator<P, f64> for PriorGenerator<P, G, R> {
+    fn raise_max_steps(&mut self, max_steps: usize) {
+        self.generator.raise_max_steps(max_steps);
+    }
+
+    fn next(&mut self) -> Option<(P, f64)> {
+        let (program, count) = self.generator.next()?;
+        let bits = self.ranking.code_bits(program.size());
+        Some((program, count as f64 * (-bits).exp2()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brainfuck::{BfNaiveGenerator, BfRanking};
+    use crate::circuit::{Circuit, CircuitRanking};
+    use crate::turing_count::{TuringCount, TuringCountRanking};
+    use crate::def::System;
+
+    // Every size gets the total weight 2^-gamma(size + 1).
+    #[test]
+    fn kraft_sum() {
+        let mut gen = PriorGenerator::new(
+            BfNaiveGenerator::new(5, false, true),
+            BfRanking::new(false, true),
+        );
+        let mut total = 0.0;
+        while let Some((_, weight)) = gen.next() {
+            total += weight;
+        }
+        // Sizes 1, 2, 3, 4, 5 have gamma codes of sizes 2..6 of 3, 3, 5, 5, 5 bits.
+        let expected = 2.0 / 8.0 + 3.0 / 32.0;
+        assert!((total - expected).abs() < 1e-9);
+
+        let comp = TuringCount::new();
+        let mut gen = PriorGenerator::new(comp.generate(2), TuringCountRanking::new());
+        let mut total = 0.0;
+        while let Some((_, weight)) = gen.next() {
+            total += weight;
+        }
+        assert!((total - 2.0 / 8.0).abs() < 1e-9);
+
+        let comp = Circuit::new(2);
+        let mut gen = PriorGenerator::new(comp.generate(2), CircuitRanking::new(2));
+        let mut total = 0.0;
+        while let Some((_, weight)) = gen.next() {
+            total += weight;
+        }
+        // Sizes 0, 1, 2 have gamma codes of sizes 1..3 of 1, 3, 3 bits.
+        assert!((total - (0.5 + 2.0 / 8.0)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::def::{Generator, Ranking};
+use std::marker::PhantomData;
+
+// SplitMix64 pseudo-random number generator.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // A uniformly distributed number in 0..n.
+    pub fn below(&mut self, n: u128) -> u128 {
+        assert!(n > 0);
+        // Reject the values from the incomplete last range to avoid the bias.
+        let zone = u128::MAX - u128::MAX % n;
+        loop {
+            let x = ((self.next_u64() as u128) << 64) | self.next_u64() as u128;
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+// Generates `samples` programs of the given size, chosen uniformly at random.
+pub struct RandomGenerator<P, R: Ranking<P>> {
+    ranking: R,
+    size: usize,
+    count: u128,
+    samples: usize,
+    rng: Rng,
+    program: PhantomData<P>,
+}
+
+impl<P, R: Ranking<P>> RandomGenerator<P, R> {
+    pub fn new(ranking: R, size: usize, samples: usize, seed: u64) -> Self {
+        let count = ranking.count(size);
+        assert!(count > 0, "No programs of size {}", size);
+        RandomGenerator {
+            ranking,
+            size,
+            count,
+            samples,
+            rng: Rng::new(seed),
+            program: PhantomData,
+        }
+    }
+}
+
+impl<P, R: Ranking<P>> Generator<P> for RandomGenerator<P, R> {
+    fn next(&mut self) -> Option<(P, usize)> {
+        if self.samples == 0 {
+            return None;
+        }
+        self.samples -= 1;
+        let index = self.rng.below(self.count);
+        Some((self.ranking.unrank(self.size, index), 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bf_count::BfCount;
+    use crate::brainfuck::BfRanking;
+    use crate::def::{Sized, System};
+    use crate::stat::Stat;
+
+    #[test]
+    fn estimate_within_interval() {
+        let comp = BfCount::new();
+        let size = 6;
+
+        let mut exact: Stat<BfCount> = Stat::new();
+        let ranking = BfRanking::new(false, true);
+        for index in 0..ranking.count(size) {
+            let program = ranking.unrank(size, index);
+            exact.register(&program, &comp.execute(&program, 1000), 1);
+        }
+
+        let mut sampled: Stat<BfCount> = Stat::new_sampled();
+        let mut sampler = RandomGenerator::new(ranking, size, 20000, 1);
+        while let Some((program, weight)) = sampler.next() {
+            assert_eq!(program.size(), size);
+            sampled.register(&program, &comp.execute(&program, 1000), weight);
+        }
+
+        // With 99.9% intervals, all of the few outputs should be covered.
+        let exact_total = exact.total() as f64;
+        for (output, stat) in exact.outputs.iter() {
+            let p = stat.count as f64 / exact_total;
+            let count = sampled.outputs.get(output).map_or(0, |s| s.count);
+            let (low, high) = Stat::<BfCount>::interval(count, sampled.total(), 3.29);
+            assert!(low <= p && p <= high, "{}: {} not in [{}, {}]", output, p, low, high);
+        }
+    }
+}
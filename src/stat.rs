@@ -1,36 +1,76 @@
-use crate::def::{System, ProgResult, Sized};
-use std::collections::HashMap;
+use crate::def::{Behavior, System, ProgResult, Sized};
+use std::collections::{BTreeMap, HashMap};
 
-pub struct OutputStat<C: System> {
+pub struct OutputStat<C: System, W = usize> {
     pub min_program: C::Program,
-    pub count: usize,
+    // The shortest description of the output in bits, see `Sized::bits`.
+    pub min_bits: usize,
+    pub count: W,
 }
 
-pub struct Stat<C: System> {
-    error: usize,
-    timeout: usize,
-    invalid_output: usize,
-    pub outputs: HashMap<C::Output, OutputStat<C>>,
+// The weight of a registered program: either the number of programs it represents, or their
+// total probability under the universal prior. The statistics are printed differently for each.
+pub trait Weight: Copy + Default + std::ops::AddAssign {
+    fn print<C: System>(stat: &Stat<C, Self>);
+}
+
+impl Weight for usize {
+    fn print<C: System>(stat: &Stat<C, Self>) {
+        stat.print_counts();
+    }
+}
+
+impl Weight for f64 {
+    fn print<C: System>(stat: &Stat<C, Self>) {
+        stat.print_prior();
+    }
+}
+
+pub struct Stat<C: System, W = usize> {
+    error: W,
+    timeout: W,
+    non_halting: W,
+    invalid_output: W,
+    // The non-halting programs by their behaviour.
+    behaviors: BTreeMap<Behavior, W>,
+    pub outputs: HashMap<C::Output, OutputStat<C, W>>,
     runs: Vec<usize>,
+    // The runs and the outputs by the length of their shortest description in bits.
+    runs_by_bits: Vec<usize>,
+    outputs_by_bits: Vec<usize>,
+    // Whether the programs are a random sample, in which case the probabilities are printed
+    // with confidence intervals.
+    sampled: bool,
 }
 
-impl<C: System> Stat<C> {
+// z-score of the two-sided 95% confidence interval.
+const Z_95: f64 = 1.96;
+
+impl<C: System, W: Weight> Stat<C, W> {
     pub fn new() -> Self {
         Stat {
-            error: 0,
-            timeout: 0,
-            invalid_output: 0,
+            error: W::default(),
+            timeout: W::default(),
+            non_halting: W::default(),
+            invalid_output: W::default(),
+            behaviors: BTreeMap::new(),
             outputs: HashMap::new(),
             runs: Vec::new(),
+            runs_by_bits: Vec::new(),
+            outputs_by_bits: Vec::new(),
+            sampled: false,
         }
     }
 
-    pub fn register(&mut self, program: &C::Program, result: &ProgResult<C::Output>, weight: usize) -> bool {
-        let size = program.size();
-        while size >= self.runs.len() {
-            self.runs.push(0);
-        }
-        self.runs[size] += 1;
+    pub fn register(
+        &mut self,
+        program: &C::Program,
+        result: &ProgResult<C::Output>,
+        weight: W,
+    ) -> bool {
+        Self::bucket(&mut self.runs, program.size());
+        let bits = program.bits();
+        Self::bucket(&mut self.runs_by_bits, bits);
         let mut new = false;
         match result {
             ProgResult::Error => {
@@ -39,6 +79,10 @@ impl<C: System> Stat<C> {
             ProgResult::Timeout => {
                 self.timeout += weight;
             }
+            ProgResult::NonHalting(behavior) => {
+                self.non_halting += weight;
+                *self.behaviors.entry(*behavior).or_default() += weight;
+            }
             ProgResult::Out { ref output, steps: _ } => {
                 if C::valid_output(output) {
                     let output: C::Output = (*output).clone();
@@ -46,10 +90,22 @@ impl<C: System> Stat<C> {
                         new = true;
                         OutputStat {
                             min_program: program.clone(),
-                            count: 0,
+                            min_bits: usize::MAX,
+                            count: W::default(),
                         }
                     }
                     );
+                    // The programs are not necessarily generated in the order of size.
+                    if program.size() < entry.min_program.size() {
+                        entry.min_program = program.clone();
+                    }
+                    if bits < entry.min_bits {
+                        if entry.min_bits != usize::MAX {
+                            self.outputs_by_bits[entry.min_bits] -= 1;
+                        }
+                        entry.min_bits = bits;
+                        Self::bucket(&mut self.outputs_by_bits, bits);
+                    }
                     entry.count += weight;
                 } else {
                     self.invalid_output += weight;
@@ -60,6 +116,44 @@ impl<C: System> Stat<C> {
         new
     }
 
+    fn bucket(buckets: &mut Vec<usize>, index: usize) {
+        if index >= buckets.len() {
+            buckets.resize(index + 1, 0);
+        }
+        buckets[index] += 1;
+    }
+
+    pub fn print(&self) {
+        W::print(self);
+    }
+
+    // Prints the number of runs by program size, and the number of runs and of outputs by the
+    // description length in bits, which is comparable across systems.
+    fn print_runs(&self) {
+        println!("Runs:");
+        for (size, &runs) in self.runs.iter().enumerate() {
+            if runs > 0 {
+                println!("{}  {}", size, runs);
+            }
+        }
+        println!("Runs and outputs by bits:");
+        for (bits, &runs) in self.runs_by_bits.iter().enumerate() {
+            let outputs = self.outputs_by_bits.get(bits).copied().unwrap_or(0);
+            if runs > 0 {
+                println!("{}  {}  {}", bits, runs, outputs);
+            }
+        }
+    }
+}
+
+impl<C: System> Stat<C> {
+    pub fn new_sampled() -> Self {
+        Stat {
+            sampled: true,
+            ..Self::new()
+        }
+    }
+
     #[cfg(test)]
     pub fn matches_outputs(&self, other: &Self) -> bool {
         for (out, count1) in self.outputs.iter() {
@@ -79,26 +173,82 @@ impl<C: System> Stat<C> {
         true
     }
 
-    pub fn print(&self) {
+    #[cfg(test)]
+    pub fn matches_failures(&self, other: &Self) -> bool {
+        self.error == other.error
+            && self.timeout == other.timeout
+            && self.non_halting == other.non_halting
+            && self.invalid_output == other.invalid_output
+    }
+
+    // Total weight of the programs with valid outputs.
+    pub fn total(&self) -> usize {
+        self.outputs.values().map(|s| s.count).sum()
+    }
+
+    // Wilson score interval for the probability of an outcome that occurred `count` times out
+    // of `total`.
+    pub fn interval(count: usize, total: usize, z: f64) -> (f64, f64) {
+        let n = total as f64;
+        let p = count as f64 / n;
+        let denom = 1.0 + z * z / n;
+        let center = (p + z * z / (2.0 * n)) / denom;
+        let half = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / denom;
+        ((center - half).max(0.0), (center + half).min(1.0))
+    }
+
+    fn print_counts(&self) {
         println!("Errors: {}", self.error);
         println!("Timeout: {}", self.timeout);
+        println!("Non-halting: {}", self.non_halting);
+        for (behavior, count) in self.behaviors.iter() {
+            println!("  {}: {}", behavior, count);
+        }
+        println!("Invalid outputs: {}", self.invalid_output);
         let mut entries: Vec<_> = self.outputs.iter().collect();
         entries.sort_by(|a, b| a.0.cmp(b.0));
-        let total = entries.iter().map(|(_, v)| v.count).sum::<usize>() as f64;
+        let total = self.total();
         for (o, s) in entries {
-            println!(
-                "{}  {:.2}  {}  {}",
-                o,
-                -(s.count as f64 / total).log2(),
-                &s.min_program,
-                s.count,
-            );
-        }
-        println!("Runs:");
-        for i in 0..self.runs.len() {
-            if self.runs[i] > 0 {
-                println!("{}  {}", i, self.runs[i]);
+            let bits = -(s.count as f64 / total as f64).log2();
+            if self.sampled {
+                let (low, high) = Self::interval(s.count, total, Z_95);
+                println!(
+                    "{}  {:.2}  [{:.2}, {:.2}]  {}  {}  {} bits",
+                    o,
+                    bits,
+                    -high.log2(),
+                    -low.log2(),
+                    &s.min_program,
+                    s.count,
+                    s.min_bits,
+                );
+            } else {
+                let min_bits = s.min_bits;
+                println!("{}  {:.2}  {}  {}  {} bits", o, bits, &s.min_program, s.count, min_bits);
             }
         }
+        self.print_runs();
+    }
+}
+
+impl<C: System> Stat<C, f64> {
+    // Prints -log2 of the algorithmic probability of each output, which doesn't depend on the
+    // size limit, except for the missing contribution of the larger programs.
+    fn print_prior(&self) {
+        println!("Errors: {:.6}", self.error);
+        println!("Timeout: {:.6}", self.timeout);
+        println!("Non-halting: {:.6}", self.non_halting);
+        for (behavior, prior) in self.behaviors.iter() {
+            println!("  {}: {:.6}", behavior, prior);
+        }
+        println!("Invalid outputs: {:.6}", self.invalid_output);
+        let halting: f64 = self.outputs.values().map(|s| s.count).sum();
+        println!("Valid outputs: {:.6}", halting);
+        let mut entries: Vec<_> = self.outputs.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        for (o, s) in entries {
+            println!("{}  {:.2}  {}  {} bits", o, -s.count.log2(), &s.min_program, s.min_bits);
+        }
+        self.print_runs();
     }
 }
@@ -0,0 +1,302 @@
+use crate::bit_seq::{self, BitCodeGenerator, BitCodeRanking, BitReader, BitSeq};
+use crate::def::{Generator, ProgResult, Sized, System};
+
+#[derive(Clone, Debug)]
+struct TransducerRule {
+    new_state: usize,
+    output: BitSeq,
+}
+
+// A finite-state transducer together with its input word. The transducer is encoded in a
+// self-delimiting way, and the input word takes the rest of the code:
+//
+// * the number of states n as 1^(n-1) 0,
+// * for each state and input bit, the new state in ceil(log2 n) bits, followed by the output
+//   word with each bit b encoded as 1b, terminated by 0,
+// * the input word verbatim.
+//
+// The initial state is 0.
+#[derive(Clone, Debug)]
+pub struct TransducerProgram {
+    rules: Vec<[TransducerRule; 2]>,
+    input: BitSeq,
+}
+
+fn state_bits(nstates: usize) -> usize {
+    (usize::BITS - (nstates - 1).leading_zeros()) as usize
+}
+
+impl TransducerProgram {
+    fn parse(reader: &mut BitReader) -> Option<Self> {
+        let mut nstates = 1;
+        while reader.read()? {
+            nstates += 1;
+        }
+
+        let mut rules = Vec::new();
+        for _ in 0..nstates {
+            let rule_for_0 = Self::parse_rule(reader, nstates)?;
+            let rule_for_1 = Self::parse_rule(reader, nstates)?;
+            rules.push([rule_for_0, rule_for_1]);
+        }
+
+        let mut input = BitSeq::new();
+        while let Some(bit) = reader.read() {
+            input.push(bit);
+        }
+
+        Some(TransducerProgram { rules, input })
+    }
+
+    fn parse_rule(reader: &mut BitReader, nstates: usize) -> Option<TransducerRule> {
+        let new_state = reader.read_bits(state_bits(nstates))?;
+        if new_state >= nstates {
+            return None;
+        }
+        let mut output = BitSeq::new();
+        while reader.read()? {
+            output.push(reader.read()?);
+        }
+        Some(TransducerRule { new_state, output })
+    }
+
+    fn encode(&self) -> BitSeq {
+        let nstates = self.rules.len();
+        let mut code = BitSeq::new();
+        code.push_bits(usize::MAX, nstates - 1);
+        code.push(false);
+        for rule in self.rules.iter().flatten() {
+            code.push_bits(rule.new_state, state_bits(nstates));
+            for &bit in rule.output.0.iter() {
+                code.push(true);
+                code.push(bit);
+            }
+            code.push(false);
+        }
+        code.0.extend_from_slice(&self.input.0);
+        code
+    }
+
+    // The number of codes of `len` bits that start with `prefix`: the ways to end the number of
+    // states or the rule that the prefix stops in, followed by the other rules and any input.
+    fn completions(prefix: &[bool], len: usize) -> u128 {
+        let Some(remaining) = len.checked_sub(prefix.len()) else {
+            return 0;
+        };
+        let single = |len: usize, count: usize| {
+            let mut lengths = vec![0; remaining + 1];
+            if len <= remaining {
+                lengths[len] = count as u128;
+            }
+            lengths
+        };
+        let value = |bits: &[bool]| bits.iter().fold(0, |v, &bit| (v << 1) | bit as usize);
+        // The ways to end an output word.
+        let outputs: Vec<u128> =
+            (0..=remaining).map(|n| if n % 2 == 1 { 1 << (n / 2) } else { 0 }).collect();
+
+        let ones = prefix.iter().take_while(|&&bit| bit).count();
+        if ones == prefix.len() {
+            return (ones + 1..=len)
+                .map(|nstates| {
+                    let end = single(nstates - ones, 1);
+                    Self::complete_rules(nstates, 2 * nstates, &end, &outputs, remaining)
+                })
+                .sum();
+        }
+
+        let nstates = ones + 1;
+        let bits = state_bits(nstates);
+        let mut i = nstates;
+        for rule in 1..=2 * nstates {
+            let rules = 2 * nstates - rule;
+            if i + bits > prefix.len() {
+                // The new states that start with the bits read.
+                let missing = i + bits - prefix.len();
+                let low = value(&prefix[i..]) << missing;
+                let high = (low + (1 << missing)).min(nstates);
+                let end = single(missing, high.saturating_sub(low));
+                let end = bit_seq::convolve(&end, &outputs);
+                return Self::complete_rules(nstates, rules, &end, &outputs, remaining);
+            }
+            if value(&prefix[i..i + bits]) >= nstates {
+                return 0;
+            }
+            i += bits;
+            loop {
+                if i == prefix.len() {
+                    return Self::complete_rules(nstates, rules, &outputs, &outputs, remaining);
+                }
+                if !prefix[i] {
+                    i += 1;
+                    break;
+                }
+                if i + 1 == prefix.len() {
+                    let end = bit_seq::convolve(&single(1, 2), &outputs);
+                    return Self::complete_rules(nstates, rules, &end, &outputs, remaining);
+                }
+                i += 2;
+            }
+        }
+        // The rest is the input word.
+        1 << remaining
+    }
+
+    // The number of codes with `remaining` more bits that end with the given numbers of ways by
+    // length, followed by `rules` rules and the input word.
+    fn complete_rules(
+        nstates: usize,
+        rules: usize,
+        end: &[u128],
+        outputs: &[u128],
+        remaining: usize,
+    ) -> u128 {
+        let mut rule = vec![0; remaining + 1];
+        let bits = state_bits(nstates);
+        for n in bits..=remaining {
+            rule[n] = nstates as u128 * outputs[n - bits];
+        }
+        let mut lengths = end.to_vec();
+        for _ in 0..rules {
+            lengths = bit_seq::convolve(&lengths, &rule);
+        }
+        (0..=remaining).map(|n| lengths[n] << (remaining - n)).sum()
+    }
+}
+
+impl Sized for TransducerProgram {
+    // The size of the program is the number of bits in its encoding.
+    fn size(&self) -> usize {
+        let nstates = self.rules.len();
+        let rules: usize = self
+            .rules
+            .iter()
+            .flatten()
+            .map(|rule| state_bits(nstates) + 2 * rule.output.len() + 1)
+            .sum();
+        nstates + rules + self.input.len()
+    }
+
+    fn bits(&self) -> usize {
+        self.size()
+    }
+}
+
+impl std::fmt::Display for TransducerProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, rules) in self.rules.iter().enumerate() {
+            for (v, rule) in rules.iter().enumerate() {
+                write!(f, "{}{}:{}/{} ", i, v, rule.new_state, rule.output)?;
+            }
+        }
+        write!(f, "| {}", self.input)
+    }
+}
+
+// Calude's finite-state complexity: the output is the result of running the transducer on the
+// input word.
+pub struct Transducer {}
+
+impl Transducer {
+    pub fn new() -> Self {
+        Transducer {}
+    }
+
+    // Ranks the programs in the order of `generate`.
+    pub fn ranking(&self) -> BitCodeRanking<TransducerProgram> {
+        BitCodeRanking::new(
+            TransducerProgram::parse,
+            TransducerProgram::encode,
+            TransducerProgram::completions,
+        )
+    }
+}
+
+impl System for Transducer {
+    type Output = BitSeq;
+    type Program = TransducerProgram;
+
+    fn generate(&self, limit: usize) -> impl Generator<TransducerProgram> {
+        BitCodeGenerator::new(limit, TransducerProgram::parse)
+    }
+
+    // Each input bit is counted as a step.
+    fn execute(&self, program: &TransducerProgram, max_steps: usize) -> ProgResult<BitSeq> {
+        if program.input.len() > max_steps {
+            return ProgResult::Timeout;
+        }
+
+        let mut output = BitSeq::new();
+        let mut state = 0;
+        for &bit in program.input.0.iter() {
+            let rule = &program.rules[state][bit as usize];
+            output.0.extend_from_slice(&rule.output.0);
+            state = rule.new_state;
+        }
+
+        ProgResult::Out {
+            output,
+            steps: program.input.len(),
+        }
+    }
+
+    fn valid_output(o: &BitSeq) -> bool {
+        !o.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stat::Stat;
+
+    // One state, 0 -> 0, 1 -> 1.
+    const IDENTITY: &str = "0100110";
+
+    fn parse(code: &str) -> TransducerProgram {
+        let bits = usize::from_str_radix(code, 2).unwrap();
+        let mut reader = BitReader::new(bits, code.len());
+        let program = TransducerProgram::parse(&mut reader).unwrap();
+        assert_eq!(program.size(), code.len());
+        program
+    }
+
+    #[test]
+    fn identity() {
+        let program = parse(&format!("{}{}", IDENTITY, "0110"));
+        match Transducer::new().execute(&program, 100) {
+            ProgResult::Out { output, steps } => {
+                assert_eq!(output.to_string(), "0110");
+                assert_eq!(steps, 4);
+            }
+            _ => panic!("expected output"),
+        }
+    }
+
+    #[test]
+    fn complexity_upper_bound() {
+        let comp = Transducer::new();
+        let mut gen = comp.generate(IDENTITY.len() + 3);
+        let mut stat: Stat<Transducer> = Stat::new();
+
+        while let Some((program, weight)) = gen.next() {
+            let result = comp.execute(&program, 100);
+            stat.register(&program, &result, weight);
+        }
+
+        // Each string is produced by the identity transducer at the latest.
+        for len in 1..=3 {
+            for x in 0..(1 << len) {
+                let output = BitSeq((0..len).rev().map(|i| (x >> i) & 1 != 0).collect());
+                let min_size = stat.outputs[&output].min_program.size();
+                assert!(min_size <= IDENTITY.len() + len);
+            }
+        }
+    }
+
+    #[test]
+    fn ranking_order() {
+        let comp = Transducer::new();
+        crate::def::assert_ranking_order(&comp.ranking(), comp.generate(16));
+    }
+}
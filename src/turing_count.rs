@@ -1,7 +1,13 @@
-use crate::def::{Generator, ProgResult, Sized, System};
+use crate::bit_tape::BitTape;
+use crate::def::{
+    Behavior, Generator, ProgResult, Ranking, Resumable, RunningGenerator, Sized, System,
+};
+use crate::turing_deciders::{self, ClosedLanguage, TuringSnapshot};
+use crate::turing_macro::TuringMacroMachine;
+use std::collections::BTreeMap;
 
 #[derive(Clone, Copy)]
-struct TuringCountRule {
+pub struct TuringCountRule {
     new_state: usize,
     tape_value: bool,
     move_right: bool,
@@ -18,8 +24,11 @@ impl std::fmt::Display for TuringCountRule {
     }
 }
 
+// Marks the transitions that are not defined yet in partially enumerated programs.
+const UNDEFINED: usize = usize::MAX;
+
 const STATE_NAMES: [char; 10] = ['A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J'];
-const DIRECTIONS: [&'static str; 2] = ["<", ">"];
+const DIRECTIONS: [&str; 2] = ["<", ">"];
 
 fn get_state_name(nstates: usize, state: usize) -> char {
     if state == nstates {
@@ -36,10 +45,26 @@ pub struct TuringCountProgram {
     rules: Vec<[TuringCountRule; 2]>,
 }
 
+impl TuringCountProgram {
+    pub fn nstates(&self) -> usize {
+        self.nstates
+    }
+
+    // The rule for a state and a tape value.
+    pub fn rule(&self, state: usize, tape_value: usize) -> TuringCountRule {
+        self.rules[state][tape_value]
+    }
+}
+
 impl Sized for TuringCountProgram {
     fn size(&self) -> usize {
         self.nstates
     }
+
+    // The self-delimiting code of `TuringCountRanking`.
+    fn bits(&self) -> usize {
+        TuringCountRanking::new().code_bits(self.nstates).ceil() as usize
+    }
 }
 
 impl std::fmt::Display for TuringCountProgram {
@@ -48,7 +73,9 @@ impl std::fmt::Display for TuringCountProgram {
             for v in 0..2 {
                 let rule = self.rules[i][v];
                 write!(f, " {}{}:", get_state_name(self.nstates, i), v)?;
-                if rule.new_state == self.nstates {
+                if !rule.is_defined() {
+                    write!(f, "---")?;
+                } else if rule.new_state == self.nstates {
                     write!(f, "HALT")?;
                 } else {
                     write!(
@@ -65,75 +92,692 @@ impl std::fmt::Display for TuringCountProgram {
     }
 }
 
-pub struct TuringCountGenerator {
-    index_within_nstates: usize,
-    total_for_nstates: usize,
+// Parses the format produced by `Display`, e.g. "A0:1>B A1:HALT B0:1<A B1:---".
+impl std::str::FromStr for TuringCountProgram {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+        let nstates = tokens.len() / 2;
+        if nstates == 0 || !tokens.len().is_multiple_of(2) || nstates > STATE_NAMES.len() {
+            return Err("Expected two rules per state".to_string());
+        }
+        let parse_state = |c: char| STATE_NAMES[..nstates].iter().position(|&n| n == c);
+
+        let mut rules = vec![[TuringCountRule::from_idx(4 * nstates); 2]; nstates];
+        for (i, token) in tokens.iter().enumerate() {
+            let (name, rule) = token.split_once(':').ok_or(format!("Invalid rule: {}", token))?;
+            if name.chars().collect::<Vec<_>>() != [STATE_NAMES[i / 2], ['0', '1'][i % 2]] {
+                return Err(format!("Expected rule {}{}: {}", STATE_NAMES[i / 2], i % 2, token));
+            }
+            rules[i / 2][i % 2] = match rule {
+                "HALT" => TuringCountRule::from_idx(4 * nstates),
+                "---" => TuringCountRule {
+                    new_state: UNDEFINED,
+                    tape_value: false,
+                    move_right: false,
+                },
+                _ => {
+                    let chars: Vec<char> = rule.chars().collect();
+                    let rule = match chars[..] {
+                        [v @ ('0' | '1'), d @ ('<' | '>'), c] => {
+                            parse_state(c).map(|new_state| TuringCountRule {
+                                new_state,
+                                tape_value: v == '1',
+                                move_right: d == '>',
+                            })
+                        }
+                        _ => None,
+                    };
+                    rule.ok_or(format!("Invalid rule: {}", token))?
+                }
+            };
+        }
+
+        Ok(TuringCountProgram { nstates, rules })
+    }
+}
+
+// Machines that don't reach an undefined transition in this number of steps are not expanded
+// further by `TuringCountTreeGenerator`, unless its limit is raised.
+const TREE_MAX_STEPS: usize = 1000;
+
+// The steps that are run one at a time, with the detection of non-halting machines. The rest of
+// a longer run is accelerated by `TuringMacroMachine`.
+const SINGLE_MAX_STEPS: usize = 100_000;
+
+// Limits of the backward search, by the number of steps and the number of configurations.
+const BACKWARD_MAX_DEPTH: usize = 50;
+const BACKWARD_MAX_CONFIGURATIONS: usize = 10_000;
+
+impl TuringCountProgram {
+    // Proves that the machine never halts by searching backwards from the transitions that halt
+    // or are undefined. A partial configuration is a state with the cells that are known around
+    // the head. The search follows every transition that could lead to a partial configuration,
+    // starting from the halting ones. If all the paths end in a contradiction within the limits,
+    // and none of the configurations is consistent with the blank tape in the initial state, the
+    // halting transitions can't be reached from the start.
+    fn backward_never_halts(&self) -> bool {
+        let mut stack: Vec<(usize, i32, BTreeMap<i32, bool>, usize)> = Vec::new();
+        for state in 0..self.nstates {
+            for value in [false, true] {
+                let rule = self.rules[state][value as usize];
+                if !rule.is_defined() || rule.new_state == self.nstates {
+                    stack.push((state, 0, BTreeMap::from([(0, value)]), 0));
+                }
+            }
+        }
+
+        let mut configurations = 0;
+        while let Some((state, head, cells, depth)) = stack.pop() {
+            configurations += 1;
+            if configurations > BACKWARD_MAX_CONFIGURATIONS || depth >= BACKWARD_MAX_DEPTH {
+                return false;
+            }
+            if state == 0 && cells.values().all(|&v| !v) {
+                return false;
+            }
+
+            for previous in 0..self.nstates {
+                for value in [false, true] {
+                    let rule = self.rules[previous][value as usize];
+                    if rule.new_state != state {
+                        continue;
+                    }
+                    let position = if rule.move_right { head - 1 } else { head + 1 };
+                    if cells.get(&position).is_some_and(|&v| v != rule.tape_value) {
+                        continue;
+                    }
+                    let mut cells = cells.clone();
+                    cells.insert(position, value);
+                    stack.push((previous, position, cells, depth + 1));
+                }
+            }
+        }
+
+        true
+    }
+
+    // The same machine with left and right swapped.
+    fn mirrored(&self) -> Self {
+        let mut program = self.clone();
+        for rule in program.rules.iter_mut().flatten() {
+            rule.move_right = !rule.move_right;
+        }
+        program
+    }
+}
+
+impl TuringCountRule {
+    // Rule #idx out of 4 * nstates + 1. All the rules leading to the terminal state are
+    // equivalent, so only one of them is used.
+    fn from_idx(idx: usize) -> Self {
+        TuringCountRule {
+            new_state: idx / 4,
+            tape_value: (idx & 2) != 0,
+            move_right: (idx & 1) != 0,
+        }
+    }
+
+    pub fn is_defined(&self) -> bool {
+        self.new_state != UNDEFINED
+    }
+
+    pub fn new_state(&self) -> usize {
+        self.new_state
+    }
+
+    pub fn tape_value(&self) -> bool {
+        self.tape_value
+    }
+
+    pub fn move_right(&self) -> bool {
+        self.move_right
+    }
+
+    fn to_idx(self, nstates: usize) -> usize {
+        assert!(self.is_defined());
+        if self.new_state == nstates {
+            4 * nstates
+        } else {
+            self.new_state * 4 + (self.tape_value as usize) * 2 + self.move_right as usize
+        }
+    }
+}
+
+// Ranks the full transition tables of the machines with a given number of states. The rule for
+// state 0 and tape value 0 is the least significant digit of the index in base 4 * nstates + 1.
+#[derive(Clone)]
+pub struct TuringCountRanking {}
+
+impl TuringCountRanking {
+    pub fn new() -> Self {
+        TuringCountRanking {}
+    }
+}
+
+impl Ranking<TuringCountProgram> for TuringCountRanking {
+    fn count(&self, nstates: usize) -> u128 {
+        ((4 * nstates + 1) as u128).pow(2 * nstates as u32)
+    }
+
+    fn rank(&self, program: &TuringCountProgram) -> u128 {
+        let base = (4 * program.nstates + 1) as u128;
+        program
+            .rules
+            .iter()
+            .flatten()
+            .rev()
+            .fold(0, |index, rule| index * base + rule.to_idx(program.nstates) as u128)
+    }
+
+    fn unrank(&self, nstates: usize, mut index: u128) -> TuringCountProgram {
+        assert!(index < self.count(nstates));
+        let base = (4 * nstates + 1) as u128;
+        let mut next_rule = || {
+            let rule = TuringCountRule::from_idx((index % base) as usize);
+            index /= base;
+            rule
+        };
+        let rules = (0..nstates).map(|_| [next_rule(), next_rule()]).collect();
+        TuringCountProgram { nstates, rules }
+    }
+
+    fn max_size(&self) -> usize {
+        STATE_NAMES.len()
+    }
+
+    fn check(&self, program: &TuringCountProgram) -> Result<(), String> {
+        if program.rules.iter().flatten().all(|rule| rule.is_defined()) {
+            Ok(())
+        } else {
+            Err("The program has undefined transitions".to_string())
+        }
+    }
+}
+
+// The state of a running machine.
+#[derive(Clone)]
+struct TuringCountMachine {
+    tape: BitTape,
+    position: i32,
+    state: usize,
+    output: u64,
+    step: usize,
+}
+
+impl TuringCountMachine {
+    fn new() -> Self {
+        // The cells on both sides of the initial position are accessed from the start.
+        let mut tape = BitTape::new();
+        tape.extend(-1);
+        tape.extend(0);
+        TuringCountMachine {
+            tape,
+            position: 0,
+            state: 0,
+            output: 0,
+            step: 0,
+        }
+    }
+
+    fn read(&mut self) -> usize {
+        self.tape.read(self.position as isize) as usize
+    }
+
+    // Should be called after `read`, which makes sure that the current cell exists.
+    fn apply(&mut self, rule: &TuringCountRule) {
+        self.state = rule.new_state;
+        self.tape.set(self.position as isize, rule.tape_value);
+
+        if rule.move_right {
+            self.position += 1;
+        } else {
+            self.position -= 1;
+        }
+
+        if self.state == 0 {
+            self.output += 1;
+        }
+
+        self.step += 1;
+    }
+}
+
+impl TuringCountMachine {
+    // The value of a cell, without extending the tape.
+    fn cell(&self, position: i32) -> bool {
+        self.tape.get(position as isize)
+    }
+
+    // Continues the run on a tape compressed into blocks.
+    fn accelerate(&self) -> TuringMacroMachine {
+        let head = self.position as isize;
+        TuringMacroMachine::new(self.state, &self.tape, head, self.step, self.output)
+    }
+
+    fn same_configuration(&self, other: &Self) -> bool {
+        self.state == other.state
+            && self.position == other.position
+            && self.tape == other.tape
+    }
+
+    // Whether the head is at a cell to the right (or to the left) of all the visited cells. All
+    // the cells beyond it are then blank.
+    fn at_record(&self, right: bool) -> bool {
+        if right {
+            self.position as isize == self.tape.end()
+        } else {
+            self.position as isize == self.tape.start() - 1
+        }
+    }
+}
+
+// A machine saved at a record on one side of the tape, with the furthest position towards the
+// other side that has been visited since.
+#[derive(Clone)]
+struct TuringCountRecord {
+    machine: TuringCountMachine,
+    furthest: i32,
+}
+
+// Proves that a machine never halts by finding a cycle or a translated cycle. The machine is
+// saved at exponentially spaced steps, as in Brent's cycle detection, and a cycle is found when
+// the same configuration is reached again.
+//
+// A translated cycle is found between two records on the same side in the same state. If the
+// cells from the furthest position visited in between up to the head are the same, shifted by
+// the distance between the records, the machine repeats the same computation shifted by this
+// distance forever, since the cells beyond the records are blank.
+#[derive(Clone)]
+struct TuringCycleDetector {
+    steps: usize,
+    saved: Option<TuringCountMachine>,
+    // The number of records and the saved record on each side, left and right.
+    records: [usize; 2],
+    saved_records: [Option<TuringCountRecord>; 2],
+}
+
+impl TuringCycleDetector {
+    fn new() -> Self {
+        TuringCycleDetector {
+            steps: 0,
+            saved: None,
+            records: [0, 0],
+            saved_records: [None, None],
+        }
+    }
+
+    // Registers the machine before a step. Returns the behaviour if it never halts.
+    fn check(&mut self, machine: &TuringCountMachine) -> Option<Behavior> {
+        if let Some(saved) = &self.saved {
+            if saved.same_configuration(machine) {
+                return Some(Behavior::Cycler);
+            }
+        }
+        self.steps += 1;
+        if self.steps.is_power_of_two() {
+            self.saved = Some(machine.clone());
+        }
+
+        for right in [false, true] {
+            let side = right as usize;
+            if let Some(record) = &mut self.saved_records[side] {
+                record.furthest = if right {
+                    record.furthest.min(machine.position)
+                } else {
+                    record.furthest.max(machine.position)
+                };
+            }
+            if !machine.at_record(right) {
+                continue;
+            }
+
+            if let Some(record) = &self.saved_records[side] {
+                let saved = &record.machine;
+                if saved.state == machine.state {
+                    let shift = machine.position - saved.position;
+                    let (from, to) = if right {
+                        (record.furthest, saved.position)
+                    } else {
+                        (saved.position, record.furthest)
+                    };
+                    if (from..=to).all(|x| saved.cell(x) == machine.cell(x + shift)) {
+                        return Some(Behavior::TranslatedCycler);
+                    }
+                }
+            }
+            self.records[side] += 1;
+            if self.records[side].is_power_of_two() {
+                self.saved_records[side] = Some(TuringCountRecord {
+                    machine: machine.clone(),
+                    furthest: machine.position,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+// The number of snapshots kept on each side by `TuringHistory`.
+const HISTORY_SNAPSHOTS: usize = 8;
+
+// Keeps the last snapshots of the machine at each end of the visited part of the tape, for the
+// deciders in `turing_deciders`. The snapshots on the left end are mirrored.
+#[derive(Clone)]
+struct TuringHistory {
+    // The leftmost and the rightmost visited positions.
+    visited: [i32; 2],
+    snapshots: [Vec<TuringSnapshot>; 2],
+    // Whether snapshots were taken since the last decision.
+    fresh: bool,
+}
+
+impl TuringHistory {
+    fn new() -> Self {
+        TuringHistory {
+            visited: [0, 0],
+            snapshots: [Vec::new(), Vec::new()],
+            fresh: false,
+        }
+    }
+
+    // Registers the machine before a step by the rule. A snapshot is taken when the head turns
+    // back at an end.
+    fn record(&mut self, machine: &TuringCountMachine, rule: &TuringCountRule) {
+        self.visited[0] = self.visited[0].min(machine.position);
+        self.visited[1] = self.visited[1].max(machine.position);
+        let side = !rule.move_right as usize;
+        if machine.position != self.visited[side] {
+            return;
+        }
+
+        let [left, right] = self.visited;
+        let mut tape: Vec<bool> = (left..=right).map(|x| machine.cell(x)).collect();
+        if side == 0 {
+            tape.reverse();
+        }
+        let snapshots = &mut self.snapshots[side];
+        if snapshots.len() == HISTORY_SNAPSHOTS {
+            snapshots.remove(0);
+        }
+        snapshots.push(TuringSnapshot { state: machine.state, tape });
+        self.fresh = true;
+    }
+
+    // Proves that the machine never halts from the snapshots. The same snapshots are not tried
+    // twice.
+    fn decide(&mut self, program: &TuringCountProgram) -> Option<Behavior> {
+        if !self.fresh {
+            return None;
+        }
+        self.fresh = false;
+        turing_deciders::decide(&program.mirrored(), &self.snapshots[0])
+            .or_else(|| turing_deciders::decide(program, &self.snapshots[1]))
+    }
+}
+
+// Enumerates the machines in tree normal form. A machine starts with all the transitions
+// undefined, and is run until it reaches an undefined transition. Then it is branched into
+// 4 * nstates + 1 machines, one for every possible definition of this transition. The machines
+// that halt or run for `max_steps` steps without reaching an undefined transition are returned,
+// with the weight equal to the number of full transition tables that they represent.
+//
+// With `reduce_symmetry`, only one machine is generated out of each class of machines that are
+// the same up to renaming the non-initial states or swapping left and right, neither of which
+// changes the output. States are numbered in the order in which they are first used, so a
+// transition can only go to one of the states that are not used yet, which stands for all of
+// them. The first transition can only move right, unless it halts. The weight is multiplied by
+// the size of the class.
+//
+// The results are exact as long as the programs are executed with at most `max_steps` steps.
+// Reaching an undefined transition during execution is reported as a timeout. A larger limit can
+// be set with `raise_max_steps` between the programs: the executions of the machines that are not
+// returned yet are resumed with it, so the machines are branched further instead.
+pub struct TuringCountTreeGenerator {
     max_states: usize,
+    max_steps: usize,
+    reduce_symmetry: bool,
     nstates: usize,
+    // Partially defined programs together with their executions up to the first undefined
+    // transition, and the number of programs that each of them represents due to symmetry.
+    stack: Vec<(TuringCountProgram, TuringCountExecution, usize)>,
 }
 
-impl TuringCountGenerator {
-    fn new(max_states: usize) -> Self {
-        TuringCountGenerator {
+impl TuringCountTreeGenerator {
+    pub fn new(max_states: usize, max_steps: usize, reduce_symmetry: bool) -> Self {
+        TuringCountTreeGenerator {
             max_states,
-            index_within_nstates: 0,
-            total_for_nstates: 0,
+            max_steps,
+            reduce_symmetry,
             nstates: 0,
+            stack: Vec::new(),
         }
     }
 
     fn increment_nstates(&mut self) {
         self.nstates += 1;
-        self.index_within_nstates = 0;
-        self.total_for_nstates = 1;
+        println!("nstates: {}", self.nstates);
+        let undefined = TuringCountRule {
+            new_state: UNDEFINED,
+            tape_value: false,
+            move_right: false,
+        };
+        let program = TuringCountProgram {
+            nstates: self.nstates,
+            rules: vec![[undefined; 2]; self.nstates],
+        };
+        self.stack.push((program, TuringCountExecution::new(), 1));
+    }
+}
 
-        for _ in 0..self.nstates {
-            self.total_for_nstates *= (4 * self.nstates + 1) * (4 * self.nstates + 1);
-        }
-        println!(
-            "nstates: {}, total machines: {}",
-            self.nstates, self.total_for_nstates
-        );
+impl Generator<TuringCountProgram> for TuringCountTreeGenerator {
+    // The programs that are still partial are run with the larger limit, and branched if they
+    // reach an undefined transition.
+    fn raise_max_steps(&mut self, max_steps: usize) {
+        self.max_steps = self.max_steps.max(max_steps);
+    }
+
+    fn next(&mut self) -> Option<(TuringCountProgram, usize)> {
+        self.next_run().map(|(program, _, weight)| (program, weight))
     }
+}
 
-    fn rule_from_idx(&self, idx: &mut usize) -> TuringCountRule {
-        let rule_idx = *idx % (4 * self.nstates + 1);
-        *idx /= 4 * self.nstates + 1;
+impl RunningGenerator<TuringCountProgram, u64> for TuringCountTreeGenerator {
+    fn next_run(&mut self) -> Option<(TuringCountProgram, ProgResult<u64>, usize)> {
+        loop {
+            if self.stack.is_empty() {
+                if self.nstates >= self.max_states {
+                    return None;
+                }
+                self.increment_nstates();
+            }
 
-        let new_state = rule_idx / 4;
-        let tape_value = (rule_idx & 2) != 0;
-        let move_right = (rule_idx & 1) != 0;
+            let (program, mut execution, multiplier) = self.stack.pop().unwrap();
+            let nstates = program.nstates;
+            let result = match execution.run(&program, self.max_steps) {
+                Some(ProgResult::Timeout) => {
+                    // Push in reverse, so that the machines are returned in the order of rule
+                    // indices.
+                    let (state, tape_value) = execution.undefined(&program);
+                    let children = branches(&program, state, tape_value, self.reduce_symmetry);
+                    for (child, symmetry) in children.into_iter().rev() {
+                        self.stack.push((child, execution.branch(), multiplier * symmetry));
+                    }
+                    continue;
+                }
+                Some(result) => result,
+                None => ProgResult::Timeout,
+            };
 
-        TuringCountRule {
-            new_state,
-            tape_value,
-            move_right,
+            let undefined = program.rules.iter().flatten().filter(|r| !r.is_defined()).count();
+            let weight = multiplier * (4 * nstates + 1).pow(undefined as u32);
+            return Some((program, result, weight));
         }
     }
 }
 
-impl Generator<TuringCountProgram> for TuringCountGenerator {
-    fn next(&mut self) -> Option<(TuringCountProgram, usize)> {
-        if self.index_within_nstates >= self.total_for_nstates {
-            self.increment_nstates();
+// Returns the number of programs represented by the given definition of the next
+// transition, or 0 if it is not canonical.
+fn symmetry_multiplier(program: &TuringCountProgram, rule: &TuringCountRule) -> usize {
+    if rule.new_state == program.nstates {
+        return 1;
+    }
+
+    let mut used = vec![false; program.nstates];
+    used[0] = true;
+    let mut first = true;
+    for r in program.rules.iter().flatten().filter(|r| r.is_defined()) {
+        first = false;
+        if r.new_state < program.nstates {
+            used[r.new_state] = true;
         }
-        if self.nstates > self.max_states {
-            return None;
+    }
+
+    if first && !rule.move_right {
+        return 0;
+    }
+    let mirror = if first { 2 } else { 1 };
+
+    if used[rule.new_state] {
+        return mirror;
+    }
+    let fresh = used.iter().position(|&u| !u).unwrap();
+    if rule.new_state != fresh {
+        return 0;
+    }
+    mirror * used.iter().filter(|&&u| !u).count()
+}
+
+// The programs that define the given undefined transition of a program, in the order of rule
+// indices, each with the number of programs that it represents. With `reduce_symmetry`, only the
+// canonical definitions are kept, as in `TuringCountTreeGenerator`.
+fn branches(
+    program: &TuringCountProgram,
+    state: usize,
+    tape_value: usize,
+    reduce_symmetry: bool,
+) -> Vec<(TuringCountProgram, usize)> {
+    (0..4 * program.nstates + 1)
+        .map(TuringCountRule::from_idx)
+        .filter_map(|rule| {
+            let symmetry = if reduce_symmetry { symmetry_multiplier(program, &rule) } else { 1 };
+            if symmetry == 0 {
+                return None;
+            }
+            let mut child = program.clone();
+            child.rules[state][tape_value] = rule;
+            Some((child, symmetry))
+        })
+        .collect()
+}
+
+// Runs a step of an execution. Returns the result if the execution stops at this step. An
+// undefined transition is found before anything is registered, so that the execution can
+// continue from the same configuration once the transition is defined.
+fn step(
+    program: &TuringCountProgram,
+    machine: &mut TuringCountMachine,
+    detector: &mut TuringCycleDetector,
+    history: &mut TuringHistory,
+) -> Option<ProgResult<u64>> {
+    if !program.rules[machine.state][machine.cell(machine.position) as usize].is_defined() {
+        return Some(ProgResult::Timeout);
+    }
+    if let Some(behavior) = detector.check(machine) {
+        return Some(ProgResult::NonHalting(behavior));
+    }
+    let tape_value = machine.read();
+    let rule = &program.rules[machine.state][tape_value];
+    history.record(machine, rule);
+    machine.apply(rule);
+    None
+}
+
+// An execution that can be resumed with a larger step limit.
+#[derive(Clone)]
+pub struct TuringCountExecution {
+    machine: TuringCountMachine,
+    detector: TuringCycleDetector,
+    history: TuringHistory,
+    // The result of `backward_never_halts`, which only depends on the program.
+    never_halts: Option<bool>,
+    // The run past the single steps.
+    accelerated: Option<TuringMacroMachine>,
+}
+
+impl TuringCountExecution {
+    fn new() -> Self {
+        TuringCountExecution {
+            machine: TuringCountMachine::new(),
+            detector: TuringCycleDetector::new(),
+            history: TuringHistory::new(),
+            never_halts: None,
+            accelerated: None,
         }
-        let mut idx = self.index_within_nstates;
-        let mut program = TuringCountProgram {
-            nstates: self.nstates,
-            rules: Vec::new(),
-        };
-        for _istate in 0..self.nstates {
-            let rule_for_0 = self.rule_from_idx(&mut idx);
-            let rule_for_1 = self.rule_from_idx(&mut idx);
+    }
+
+    // Runs the program until it stops or reaches `max_steps`. Returns None at the limit, and a
+    // timeout if it reaches an undefined transition.
+    fn run(&mut self, program: &TuringCountProgram, max_steps: usize) -> Option<ProgResult<u64>> {
+        if let Some(accelerated) = &mut self.accelerated {
+            return accelerated.run(program, max_steps);
+        }
+        let TuringCountExecution { machine, detector, history, .. } = self;
+        while machine.step < max_steps.min(SINGLE_MAX_STEPS) && machine.state < program.nstates {
+            if let Some(result) = step(program, machine, detector, history) {
+                return Some(result);
+            }
+        }
+        self.finish(program, max_steps)
+    }
+
+    // The undefined transition that the execution stopped at, by state and symbol.
+    fn undefined(&mut self, program: &TuringCountProgram) -> (usize, usize) {
+        match &mut self.accelerated {
+            Some(accelerated) => accelerated.undefined(program).unwrap(),
+            None => (self.machine.state, self.machine.cell(self.machine.position) as usize),
+        }
+    }
 
-            program.rules.push([rule_for_0, rule_for_1]);
+    // The execution of a program that defines the transition that this one stopped at. It
+    // continues from the same configuration, except after the acceleration, whose blocks depend
+    // on the transitions. The deciders are tried again, since the new transition can change
+    // their results.
+    fn branch(&self) -> Self {
+        match self.accelerated {
+            Some(_) => TuringCountExecution::new(),
+            None => {
+                let mut execution = self.clone();
+                execution.history.fresh = true;
+                execution.never_halts = None;
+                execution
+            }
         }
-        self.index_within_nstates += 1;
+    }
 
-        Some((program, 1))
+    // The result after the single steps, when the machine halts or reaches their limit.
+    fn finish(
+        &mut self,
+        program: &TuringCountProgram,
+        max_steps: usize,
+    ) -> Option<ProgResult<u64>> {
+        let machine = &self.machine;
+        if machine.state == program.nstates {
+            (machine.step < max_steps).then_some(ProgResult::Out {
+                output: machine.output,
+                steps: machine.step,
+            })
+        } else if *self.never_halts.get_or_insert_with(|| program.backward_never_halts()) {
+            Some(ProgResult::NonHalting(Behavior::Unreachable))
+        } else if let Some(behavior) = self.history.decide(program) {
+            Some(ProgResult::NonHalting(behavior))
+        } else if machine.step < max_steps {
+            self.accelerated.insert(machine.accelerate()).run(program, max_steps)
+        } else {
+            None
+        }
     }
 }
 
@@ -143,6 +787,11 @@ impl TuringCount {
     pub fn new() -> Self {
         TuringCount {}
     }
+
+    // The generated machines together with their results.
+    pub fn generate_runs(&self, limit: usize) -> TuringCountTreeGenerator {
+        TuringCountTreeGenerator::new(limit, TREE_MAX_STEPS, true)
+    }
 }
 
 impl System for TuringCount {
@@ -150,65 +799,54 @@ impl System for TuringCount {
     type Program = TuringCountProgram;
 
     fn execute(&self, program: &Self::Program, max_steps: usize) -> ProgResult<u64> {
-        let mut tape_positive = vec![false];
-        let mut tape_negative = vec![false];
-        let mut position: i32 = 0;
-        let mut output = 0;
-        let mut state = 0;
-        let mut step = 0;
-
-        while step < max_steps && state < program.nstates {
-            let tape_value = if position >= 0 {
-                while tape_positive.len() <= position as usize {
-                    tape_positive.push(false);
-                }
-                tape_positive[position as usize] as usize
-            } else {
-                let pos = (-position - 1) as usize;
-                while tape_negative.len() <= pos {
-                    tape_negative.push(false);
-                }
-                tape_negative[pos] as usize
-            };
-
-            let rule = &program.rules[state][tape_value];
+        let mut execution = TuringCountExecution::new();
+        self.resume(program, &mut execution, max_steps).unwrap_or(ProgResult::Timeout)
+    }
 
-            state = rule.new_state;
-            if position >= 0 {
-                tape_positive[position as usize] = rule.tape_value;
-            } else {
-                tape_negative[(-position - 1) as usize] = rule.tape_value;
-            };
+    fn decide(&self, program: &Self::Program) -> Option<(Behavior, String)> {
+        let language = ClosedLanguage::find(program).filter(|l| l.verify(program))?;
+        Some((Behavior::ClosedLanguage, language.to_string()))
+    }
 
-            if rule.move_right {
-                position += 1;
-            } else {
-                position -= 1;
-            }
+    fn generate(&self, limit: usize) -> impl Generator<TuringCountProgram> {
+        self.generate_runs(limit)
+    }
 
-            if state == 0 {
-                output += 1;
-            }
+    fn valid_output(o: &u64) -> bool {
+        *o > 0
+    }
+}
 
-            step += 1;
-        }
+impl Resumable for TuringCount {
+    type Execution = TuringCountExecution;
 
-        if step >= max_steps {
-            ProgResult::Timeout
-        } else {
-            ProgResult::Out {
-                output,
-                steps: step,
-            }
-        }
+    fn start(&self, _program: &Self::Program) -> TuringCountExecution {
+        TuringCountExecution::new()
     }
 
-    fn generate(&self, limit: usize) -> TuringCountGenerator {
-        TuringCountGenerator::new(limit)
+    fn resume(
+        &self,
+        program: &Self::Program,
+        execution: &mut TuringCountExecution,
+        max_steps: usize,
+    ) -> Option<ProgResult<u64>> {
+        execution.run(program, max_steps)
     }
 
-    fn valid_output(o: &u64) -> bool {
-        *o > 0
+    // The machines that `generate` would return with a larger limit, with the weights that they
+    // take over from the given one.
+    fn expand(
+        &self,
+        program: &Self::Program,
+        execution: &mut TuringCountExecution,
+        weight: usize,
+    ) -> Vec<(Self::Program, TuringCountExecution, usize)> {
+        let (state, tape_value) = execution.undefined(program);
+        let weight = weight / (4 * program.nstates + 1);
+        branches(program, state, tape_value, true)
+            .into_iter()
+            .map(|(child, symmetry)| (child, execution.branch(), weight * symmetry))
+            .collect()
     }
 }
 
@@ -252,9 +890,9 @@ mod test {
         fn rule_from_idx(&self, idx: &mut usize) -> TuringCountRule {
             let new_state = *idx % (self.nstates + 1);
             *idx /= self.nstates + 1;
-            let tape_value = (*idx % 2) != 0;
+            let tape_value = !(*idx).is_multiple_of(2);
             *idx /= 2;
-            let move_right = (*idx % 2) != 0;
+            let move_right = !(*idx).is_multiple_of(2);
             *idx /= 2;
 
             TuringCountRule {
@@ -303,6 +941,281 @@ mod test {
         }
     }
 
+    // Runs the machine without any deciders.
+    fn reference_execute(program: &TuringCountProgram, max_steps: usize) -> ProgResult<u64> {
+        let mut machine = TuringCountMachine::new();
+        while machine.step < max_steps && machine.state < program.nstates {
+            let tape_value = machine.read();
+            let rule = &program.rules[machine.state][tape_value];
+            if !rule.is_defined() {
+                return ProgResult::Timeout;
+            }
+            machine.apply(rule);
+        }
+        if machine.step >= max_steps {
+            ProgResult::Timeout
+        } else {
+            ProgResult::Out { output: machine.output, steps: machine.step }
+        }
+    }
+
+    #[test]
+    fn detects_cyclers() {
+        let comp = TuringCount::new();
+        let result = |source: &str| comp.execute(&source.parse().unwrap(), 100_000);
+
+        // A cycler, translated cyclers to the right and to the left, and a translated cycler
+        // that goes back over the cells it wrote.
+        for (source, behavior) in [
+            ("A0:0>B A1:--- B0:0<A B1:---", Behavior::Cycler),
+            ("A0:1>A A1:---", Behavior::TranslatedCycler),
+            ("A0:1<B A1:--- B0:0<A B1:---", Behavior::TranslatedCycler),
+            ("A0:1>B A1:0<B B0:1<A B1:1>A", Behavior::TranslatedCycler),
+        ] {
+            let result = result(source);
+            assert!(matches!(result, ProgResult::NonHalting(b) if b == behavior), "{}", source);
+        }
+    }
+
+    #[test]
+    fn detects_bouncers_and_counters() {
+        let comp = TuringCount::new();
+        let result = |source: &str| comp.execute(&source.parse().unwrap(), 10_000);
+
+        // Bouncers that grow the tape on one side and on both sides, and one that zigzags
+        // between the cells it passes over. Counters with the digits on the right of the head,
+        // and on the left.
+        for (source, behavior) in [
+            ("A0:0>B A1:0<B B0:1<A B1:0>C C0:1>B C1:---", Behavior::Bouncer),
+            ("A0:1>B A1:--- B0:0<C B1:0>A C0:1<A C1:1<B", Behavior::Bouncer),
+            ("A0:1>B A1:0<B B0:1<C B1:1>B C0:--- C1:1<A", Behavior::Bouncer),
+            ("A0:0>B A1:0<A B0:1<A B1:1>C C0:1>B C1:---", Behavior::Counter),
+            ("A0:1>B A1:1<C B0:0<A B1:0>B C0:1<A C1:---", Behavior::Counter),
+        ] {
+            let result = result(source);
+            assert!(matches!(result, ProgResult::NonHalting(b) if b == behavior), "{}", source);
+        }
+        // A machine with a more complex behaviour is not decided.
+        let undecided = "A0:0>B A1:0<C B0:1<A B1:1>B C0:0<D C1:0<A D0:0>A D1:---";
+        assert!(matches!(result(undecided), ProgResult::Timeout));
+    }
+
+    #[test]
+    fn closed_languages() {
+        let comp = TuringCount::new();
+
+        let program: TuringCountProgram =
+            "A0:0>B A1:0<C B0:1<A B1:1>B C0:0>D C1:1>B D0:1>C D1:---".parse().unwrap();
+        assert!(matches!(comp.execute(&program, 10_000), ProgResult::Timeout));
+        let (behavior, certificate) = comp.decide(&program).unwrap();
+        assert_eq!(behavior, Behavior::ClosedLanguage);
+        assert!(certificate.starts_with("000 001 00A0 00A1 "));
+
+        // The machines that halt have no closed language.
+        for source in ["A0:1>B A1:1<B B0:1<A B1:HALT", "A0:0>B A1:--- B0:1<A B1:HALT"] {
+            assert!(comp.decide(&source.parse().unwrap()).is_none(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn accelerated_runs() {
+        // The macro machine gives the same results as the reference, from the start and from
+        // a configuration reached by single steps.
+        let out = |result: &ProgResult<u64>| match *result {
+            ProgResult::Out { output, steps } => Some((output, steps)),
+            _ => None,
+        };
+        let mut gen = TuringCountTreeGenerator::new(3, 100, true);
+        while let Some((program, _)) = gen.next() {
+            let expected = reference_execute(&program, 500);
+            for steps in [0, 10] {
+                let mut machine = TuringCountMachine::new();
+                while machine.step < steps && machine.state < program.nstates {
+                    let rule = program.rules[machine.state][machine.read()];
+                    if !rule.is_defined() {
+                        break;
+                    }
+                    machine.apply(&rule);
+                }
+                if machine.state == program.nstates || machine.step < steps {
+                    continue;
+                }
+                let result = machine.accelerate().run(&program, 500);
+                assert_eq!(result.and_then(|r| out(&r)), out(&expected), "{}", program);
+            }
+        }
+
+        // The busy beaver with 5 states.
+        let program = "A0:1>B A1:1<C B0:1>C B1:1>B C0:1>D C1:0<E D0:1<A D1:1<D E0:HALT E1:0<A";
+        let program: TuringCountProgram = program.parse().unwrap();
+        let result = TuringCount::new().execute(&program, 100_000_000);
+        assert!(matches!(result, ProgResult::Out { steps: 47_176_870, .. }));
+    }
+
+    #[test]
+    fn backward_reasoning() {
+        let never_halts = |source: &str| {
+            source.parse::<TuringCountProgram>().unwrap().backward_never_halts()
+        };
+
+        // Only zeros are written, so the halting transitions on 1 can't be reached.
+        assert!(never_halts("A0:0>B A1:HALT B0:0<A B1:HALT"));
+        // B is entered only on cells that A has left blank or cleared, so B1 can't be reached.
+        assert!(never_halts("A0:1>B A1:0<A B0:1<A B1:HALT"));
+        assert!(!never_halts("A0:1>B A1:HALT B0:1<A B1:---"));
+        // The halting transition can be reached from a non-blank tape, and the search doesn't
+        // end.
+        assert!(!never_halts("A0:1>A A1:HALT"));
+    }
+
+    // The machines that are proven not to halt run without halting for much longer.
+    #[test]
+    fn non_halting_is_sound() {
+        let comp = TuringCount::new();
+        let mut gen = TuringCountTreeGenerator::new(3, 100, true);
+
+        while let Some((program, _)) = gen.next() {
+            let decided = match comp.execute(&program, 1000) {
+                ProgResult::NonHalting(_) => true,
+                ProgResult::Timeout => comp.decide(&program).is_some(),
+                _ => false,
+            };
+            if decided {
+                let result = reference_execute(&program, 5000);
+                assert!(matches!(result, ProgResult::Timeout), "{}", program);
+            }
+        }
+    }
+
+    #[test]
+    fn resumed_executions() {
+        // Resuming with larger limits gives the same results as executing with each of them.
+        let comp = TuringCount::new();
+        let mut gen = TuringCountTreeGenerator::new(3, 1000, true);
+        while let Some((program, _)) = gen.next() {
+            let mut execution = comp.start(&program);
+            for max_steps in [5, 50, 500] {
+                let result = comp.resume(&program, &mut execution, max_steps);
+                let stopped = result.is_some();
+                let expected = comp.execute(&program, max_steps);
+                let result = result.unwrap_or(ProgResult::Timeout);
+                assert_eq!(format!("{:?}", result), format!("{:?}", expected), "{}", program);
+                if stopped {
+                    break;
+                }
+            }
+        }
+
+        // The busy beaver with 5 states, resumed past the single steps.
+        let program = "A0:1>B A1:1<C B0:1>C B1:1>B C0:1>D C1:0<E D0:1<A D1:1<D E0:HALT E1:0<A";
+        let program: TuringCountProgram = program.parse().unwrap();
+        let mut execution = comp.start(&program);
+        assert!(comp.resume(&program, &mut execution, 1000).is_none());
+        assert!(comp.resume(&program, &mut execution, 1_000_000).is_none());
+        let result = comp.resume(&program, &mut execution, 100_000_000);
+        assert!(matches!(result, Some(ProgResult::Out { steps: 47_176_870, .. })));
+    }
+
+    #[test]
+    fn raised_max_steps() {
+        // After the raise, the machines are branched until they reach the new limit, and they
+        // still represent all the transition tables.
+        let comp = TuringCount::new();
+        let total = |gen: &mut TuringCountTreeGenerator, raise: usize| {
+            let mut total = 0;
+            let mut count = 0;
+            while let Some((program, weight)) = gen.next() {
+                if count == raise {
+                    gen.raise_max_steps(500);
+                }
+                if count > raise {
+                    let mut execution = comp.start(&program);
+                    let result = comp.resume(&program, &mut execution, 500);
+                    assert!(!matches!(result, Some(ProgResult::Timeout)), "{}", program);
+                }
+                total += weight;
+                count += 1;
+            }
+            total
+        };
+        let expected = total(&mut TuringCountTreeGenerator::new(3, 5, true), usize::MAX);
+        assert_eq!(total(&mut TuringCountTreeGenerator::new(3, 5, true), 100), expected);
+    }
+
+    #[test]
+    fn expanded_executions() {
+        // The machines that reach an undefined transition only after the tree limit are
+        // expanded, and get the same outputs as those generated with the larger limit.
+        let comp = TuringCount::new();
+        let mut gen = TuringCountTreeGenerator::new(3, 5, true);
+        let mut stat1: Stat<TuringCount> = Stat::new();
+        let mut expanded = 0;
+        while let Some((program, weight)) = gen.next() {
+            let execution = comp.start(&program);
+            let mut pending = vec![(program, execution, weight)];
+            while let Some((program, mut execution, weight)) = pending.pop() {
+                let result = comp.resume(&program, &mut execution, 100);
+                let result = result.unwrap_or(ProgResult::Timeout);
+                let children = match result {
+                    ProgResult::Timeout => comp.expand(&program, &mut execution, weight),
+                    _ => Vec::new(),
+                };
+                if children.is_empty() {
+                    stat1.register(&program, &result, weight);
+                }
+                expanded += children.len();
+                pending.extend(children);
+            }
+        }
+        assert!(expanded > 0);
+
+        let mut gen = TuringCountTreeGenerator::new(3, 100, true);
+        let mut stat2: Stat<TuringCount> = Stat::new();
+        while let Some((program, weight)) = gen.next() {
+            stat2.register(&program, &comp.execute(&program, 100), weight);
+        }
+        assert!(stat1.matches_outputs(&stat2));
+        assert!(stat1.matches_failures(&stat2));
+    }
+
+    #[test]
+    fn generated_results() {
+        // The tree generator returns the results of the machines with the current limit, also
+        // for the executions that it resumes after a raise.
+        let comp = TuringCount::new();
+        let mut gen = TuringCountTreeGenerator::new(3, 5, true);
+        let mut count = 0;
+        loop {
+            let max_steps = if count < 100 { 5 } else { 200 };
+            gen.raise_max_steps(max_steps);
+            let Some((program, result, _)) = gen.next_run() else {
+                break;
+            };
+            let expected = comp.execute(&program, max_steps);
+            assert_eq!(format!("{:?}", result), format!("{:?}", expected), "{}", program);
+            count += 1;
+        }
+        assert!(count > 100);
+    }
+
+    #[test]
+    fn ranking_roundtrip() {
+        let ranking = TuringCountRanking::new();
+        assert_eq!(ranking.count(2), 6561);
+        for index in 0..ranking.count(2) {
+            let program = ranking.unrank(2, index);
+            assert_eq!(ranking.rank(&program), index);
+            let parsed: TuringCountProgram = program.to_string().parse().unwrap();
+            assert_eq!(ranking.rank(&parsed), index);
+        }
+
+        let parse = |s: &str| s.parse::<TuringCountProgram>();
+        assert!(ranking.check(&parse("A0:1>B A1:--- B0:0<A B1:HALT").unwrap()).is_err());
+        for s in ["A0:x?B A1:HALT", "A0:1>B A1:HALT", "A0:1> A1:HALT", "A0:1>A"] {
+            assert!(parse(s).is_err(), "{}", s);
+        }
+    }
+
     #[test]
     fn results_match() {
         let comp = TuringCount::new();
@@ -324,12 +1237,21 @@ mod test {
                 stat2.register(&program, &result, weight);
             }
 
+            let mut gen3 = TuringCountTreeGenerator::new(max_size, TREE_MAX_STEPS, false);
+            let mut stat3: Stat<TuringCount> = Stat::new();
+
+            while let Some((program, weight)) = gen3.next() {
+                let result = comp.execute(&program, 100);
+                stat3.register(&program, &result, weight);
+            }
+
             println!("stat1:");
             stat1.print();
             println!("\nstat2:");
             stat2.print();
 
             assert!(stat1.matches_outputs(&stat2));
+            assert!(stat3.matches_outputs(&stat2));
         }
     }
 }
@@ -0,0 +1,671 @@
+use crate::def::Behavior;
+use crate::turing_count::TuringCountProgram;
+use std::collections::{BTreeSet, HashSet};
+
+// Limits of the symbolic runs, by the number of steps. A pass over a repeated block counts as
+// a single step.
+const SHIFT_MAX_STEPS: usize = 1000;
+const SYMBOLIC_MAX_STEPS: usize = 10_000;
+
+// Limits of the guesses: the growth of the tape between two snapshots of a bouncer, the lengths
+// of the digits and of the fixed part around the head of a counter, and the number of fixed
+// parts on the other side of its digits.
+const BOUNCER_MAX_GROWTH: usize = 32;
+const COUNTER_MAX_DIGIT: usize = 3;
+const COUNTER_MAX_HEAD: usize = 2;
+const COUNTER_MAX_PREFIXES: usize = 8;
+
+// Limits of the search for a closed language: the width of its windows and their number.
+const LANGUAGE_MAX_WIDTH: usize = 6;
+const LANGUAGE_MAX_WINDOWS: usize = 10_000;
+
+// A configuration at the right end of the visited part of the tape, when the head turns back:
+// the state and the visited cells, the last of which is under the head. All the cells further
+// right are blank.
+#[derive(Clone)]
+pub struct TuringSnapshot {
+    pub state: usize,
+    pub tape: Vec<bool>,
+}
+
+// A part of a symbolic tape.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Segment {
+    Cell(bool),
+    // The block repeated n + count times, with the same n for all the blocks of a tape.
+    Repeat(Vec<bool>, usize),
+    // Any sequence of blocks, which the head must not enter.
+    Any,
+}
+
+fn cells(word: &[bool]) -> impl DoubleEndedIterator<Item = Segment> + '_ {
+    word.iter().map(|&v| Segment::Cell(v))
+}
+
+// Runs the machine on a single block that is entered from one side. Returns the new block if
+// the head leaves it on the other side in the same state. The machine then passes over any
+// number of copies of the block in the same way.
+fn shift(
+    program: &TuringCountProgram,
+    state: usize,
+    word: &[bool],
+    right: bool,
+) -> Option<Vec<bool>> {
+    let mut word = word.to_vec();
+    let mut current = state;
+    let mut pos = if right { 0 } else { word.len() as isize - 1 };
+    for _ in 0..SHIFT_MAX_STEPS {
+        let rule = program.rule(current, word[pos as usize] as usize);
+        if !rule.is_defined() || rule.new_state() == program.nstates() {
+            return None;
+        }
+        word[pos as usize] = rule.tape_value();
+        current = rule.new_state();
+        pos += if rule.move_right() { 1 } else { -1 };
+        if pos < 0 || pos >= word.len() as isize {
+            return (rule.move_right() == right && current == state).then_some(word);
+        }
+    }
+    None
+}
+
+// A machine running on a symbolic tape, where each step holds for all n.
+struct SymbolicMachine<'a> {
+    program: &'a TuringCountProgram,
+    state: usize,
+    // The segments left of the head, the nearest last.
+    left: Vec<Segment>,
+    // The cell under the head, and the segments right of it, the nearest last.
+    right: Vec<Segment>,
+}
+
+impl<'a> SymbolicMachine<'a> {
+    // The head is on the last cell of the tape, and the cells beyond it are blank.
+    fn new(program: &'a TuringCountProgram, state: usize, mut tape: Vec<Segment>) -> Self {
+        let head = tape.pop().unwrap();
+        SymbolicMachine { program, state, left: tape, right: vec![head] }
+    }
+
+    // Returns false if the machine may halt, or the step can't be done for all n.
+    fn step(&mut self) -> bool {
+        let Some(&Segment::Cell(value)) = self.right.last() else {
+            return false;
+        };
+        let rule = self.program.rule(self.state, value as usize);
+        if !rule.is_defined() || rule.new_state() == self.program.nstates() {
+            return false;
+        }
+        // The machine may only be able to pass over the blocks in the current state.
+        let right = rule.move_right();
+        let next = if right { self.right.iter().rev().nth(1) } else { self.left.last() };
+        if let Some(Segment::Repeat(word, _)) = next {
+            let blocked = shift(self.program, rule.new_state(), word, right).is_none();
+            if blocked && self.pass(right) {
+                return true;
+            }
+        }
+
+        self.right.pop();
+        self.state = rule.new_state();
+        if right {
+            self.left.push(Segment::Cell(rule.tape_value()));
+        } else {
+            self.right.push(Segment::Cell(rule.tape_value()));
+        }
+
+        let (from, to) = if right {
+            (&mut self.right, &mut self.left)
+        } else {
+            (&mut self.left, &mut self.right)
+        };
+        // The blocks on the way are passed over, or a copy is split off if they can't be.
+        let value = loop {
+            match from.pop() {
+                None => break false,
+                Some(Segment::Cell(value)) => break value,
+                Some(Segment::Repeat(word, count)) => {
+                    if let Some(word) = shift(self.program, self.state, &word, rule.move_right()) {
+                        to.push(Segment::Repeat(word, count));
+                    } else if count > 0 {
+                        from.push(Segment::Repeat(word.clone(), count - 1));
+                        if rule.move_right() {
+                            from.extend(cells(&word).rev());
+                        } else {
+                            from.extend(cells(&word));
+                        }
+                    } else {
+                        return false;
+                    }
+                }
+                Some(Segment::Any) => return false,
+            }
+        };
+        self.right.push(Segment::Cell(value));
+        true
+    }
+
+    // Passes over the blocks next to the head at once, starting from the cell under the head,
+    // if it is the same as the cell at their near end. The cell and the blocks are then the same
+    // as the blocks rotated by one cell, ending at the head, followed by the cell, since
+    // x (y x)^n = (x y)^n x.
+    fn pass(&mut self, right: bool) -> bool {
+        let Some(&Segment::Cell(head)) = self.right.last() else {
+            return false;
+        };
+        let next = if right { self.right.iter().rev().nth(1) } else { self.left.last() };
+        let Some(Segment::Repeat(word, count)) = next.cloned() else {
+            return false;
+        };
+        let mut rotated = word.clone();
+        if right {
+            if word.last() != Some(&head) {
+                return false;
+            }
+            rotated.rotate_right(1);
+        } else {
+            if word[0] != head {
+                return false;
+            }
+            rotated.rotate_left(1);
+        }
+        let Some(shifted) = shift(self.program, self.state, &rotated, right) else {
+            return false;
+        };
+
+        self.right.pop();
+        if right {
+            self.right.pop();
+            self.left.push(Segment::Repeat(shifted, count));
+        } else {
+            self.left.pop();
+            self.right.push(Segment::Repeat(shifted, count));
+        }
+        self.right.push(Segment::Cell(head));
+        true
+    }
+
+    // The tape up to the head, if all the cells right of it are blank.
+    fn tape(&self) -> Option<Vec<Segment>> {
+        let blank = self.right.iter().rev().skip(1).all(|s| *s == Segment::Cell(false));
+        blank.then(|| self.left.iter().chain(self.right.last()).cloned().collect())
+    }
+}
+
+// Rewrites a tape that ends with the head to a canonical form, so that the tapes with equal
+// forms are the same for all n. The blank cells at the left end are dropped, the cells are moved
+// right over the blocks by rotating the blocks, and the copies of a block that follow it are
+// counted in it.
+fn normalize(mut tape: Vec<Segment>) -> Vec<Segment> {
+    let head = tape.pop().unwrap();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 1..tape.len() {
+            let (Segment::Cell(value), Segment::Repeat(word, count)) = (&tape[i - 1], &tape[i])
+            else {
+                continue;
+            };
+            if word.last() == Some(value) {
+                let (value, mut word, count) = (*value, word.clone(), *count);
+                word.rotate_right(1);
+                tape[i - 1] = Segment::Repeat(word, count);
+                tape[i] = Segment::Cell(value);
+                changed = true;
+            }
+        }
+        for i in 0..tape.len() {
+            let Segment::Repeat(word, count) = tape[i].clone() else {
+                continue;
+            };
+            let next = i + 1..i + 1 + word.len();
+            if tape.get(next.clone()).is_some_and(|s| s.iter().cloned().eq(cells(&word))) {
+                tape[i] = Segment::Repeat(word, count + 1);
+                tape.drain(next);
+                changed = true;
+                break;
+            }
+        }
+    }
+
+    let blank = tape.iter().take_while(|s| **s == Segment::Cell(false)).count();
+    tape.drain(..blank);
+    tape.push(head);
+    tape
+}
+
+// Describes the first tape with the blocks that are inserted into it to get the second one, in
+// at most two places. The blocks are repeated as many times as they already occur there.
+fn repeated_blocks(first: &[bool], second: &[bool]) -> Option<Vec<Segment>> {
+    let growth = second.len() - first.len();
+    let prefix = first.iter().zip(second).take_while(|(a, b)| a == b).count();
+    let suffix = first.iter().rev().zip(second.iter().rev()).take_while(|(a, b)| a == b).count();
+    // The head is not in an inserted block.
+    if suffix == 0 {
+        return None;
+    }
+
+    // The positions in the first tape where the blocks are inserted, with their lengths.
+    let mut insertions = None;
+    if prefix + suffix >= first.len() {
+        insertions = Some(vec![(prefix.min(first.len() - 1), growth)]);
+    } else {
+        let end = first.len() - suffix;
+        for len in 1..growth {
+            if first[prefix..end] == second[prefix + len..end + len] {
+                insertions = Some(vec![(prefix, len), (end, growth - len)]);
+                break;
+            }
+        }
+    }
+
+    let insertions = insertions?;
+    let mut tape = Vec::new();
+    let mut pos = 0;
+    let mut inserted = 0;
+    for (k, &(i, len)) in insertions.iter().enumerate() {
+        let word = &second[i + inserted..i + inserted + len];
+        inserted += len;
+        let mut start = i;
+        while start >= pos + len && first[start - len..start] == *word {
+            start -= len;
+        }
+        tape.extend(cells(&first[pos..start]));
+        let mut count = (i - start) / len;
+        // The copies that follow end before the next block or the head.
+        let end = insertions.get(k + 1).map_or(first.len() - 1, |&(next, _)| next);
+        pos = i;
+        while pos + len <= end && first[pos..pos + len] == *word {
+            pos += len;
+            count += 1;
+        }
+        tape.push(Segment::Repeat(word.to_vec(), count));
+    }
+    tape.extend(cells(&first[pos..]));
+    Some(tape)
+}
+
+// Proves that the machine never halts, by finding a tape with repeated blocks such that if the
+// machine is in the state of the snapshot at the end of the tape with the blocks repeated n
+// times, it gets there again with the blocks repeated n + 1 times. The tape is guessed from two
+// snapshots, the first of which is the tape with n = 0.
+fn bouncer(program: &TuringCountProgram, snapshots: &[TuringSnapshot]) -> bool {
+    for (i, first) in snapshots.iter().enumerate() {
+        for second in snapshots[i + 1..].iter() {
+            let growth = second.tape.len().saturating_sub(first.tape.len());
+            if second.state != first.state || growth == 0 || growth > BOUNCER_MAX_GROWTH {
+                continue;
+            }
+            let Some(tape) = repeated_blocks(&first.tape, &second.tape) else {
+                continue;
+            };
+
+            let next: Vec<Segment> = tape
+                .iter()
+                .map(|s| match s {
+                    Segment::Repeat(word, count) => Segment::Repeat(word.clone(), count + 1),
+                    s => s.clone(),
+                })
+                .collect();
+            let next = normalize(next);
+            let mut machine = SymbolicMachine::new(program, first.state, tape);
+            for _ in 0..SYMBOLIC_MAX_STEPS {
+                if !machine.step() {
+                    break;
+                }
+                if machine.state != first.state {
+                    continue;
+                }
+                if machine.tape().is_some_and(|tape| normalize(tape) == next) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// A counter, whose tape is v X u with the head at the end of u. X is a number written with
+// the two digits, least significant last, and v is one of a few fixed parts.
+struct Counter<'a> {
+    program: &'a TuringCountProgram,
+    state: usize,
+    head: Vec<bool>,
+    // The digits zero and one.
+    digits: [Vec<bool>; 2],
+}
+
+impl Counter<'_> {
+    // Returns v if the tape is v X u, where X is followed by the rest of a number if `any`.
+    fn parse(&self, tape: &[Segment], any: bool) -> Option<Vec<bool>> {
+        let mut rest = tape.strip_suffix(&cells(&self.head).collect::<Vec<_>>()[..])?;
+        loop {
+            if let Some((Segment::Repeat(word, _), start)) = rest.split_last() {
+                if self.digits.contains(word) {
+                    rest = start;
+                    continue;
+                }
+            }
+            let len = self.digits[0].len();
+            let digit = rest.len().checked_sub(len).map(|start| &rest[start..]);
+            if digit.is_some_and(|d| self.digits.iter().any(|w| d.iter().cloned().eq(cells(w)))) {
+                rest = &rest[..rest.len() - len];
+                continue;
+            }
+            break;
+        }
+        if any {
+            rest = rest.strip_suffix(&[Segment::Any])?;
+        }
+
+        let mut prefix = Vec::new();
+        for s in rest.iter().skip_while(|s| **s == Segment::Cell(false)) {
+            let Segment::Cell(value) = s else {
+                return None;
+            };
+            prefix.push(*value);
+        }
+        Some(prefix)
+    }
+
+    // Runs the machine from v Y 0 1^n u, or from v 1^n u if there is no Y, where Y is the rest
+    // of the number, until it gets to a tape v' X u. Returns v'.
+    fn increment(&self, prefix: &[bool], carry: bool) -> Option<Vec<bool>> {
+        let mut tape: Vec<Segment> = cells(prefix).collect();
+        if carry {
+            tape.push(Segment::Any);
+            tape.extend(cells(&self.digits[0]));
+        }
+        tape.push(Segment::Repeat(self.digits[1].clone(), 0));
+        tape.extend(cells(&self.head));
+
+        let mut machine = SymbolicMachine::new(self.program, self.state, tape);
+        for _ in 0..SYMBOLIC_MAX_STEPS {
+            if !machine.step() {
+                return None;
+            }
+            if machine.state != self.state {
+                continue;
+            }
+            if let Some(prefix) = machine.tape().and_then(|tape| self.parse(&tape, carry)) {
+                return Some(prefix);
+            }
+        }
+        None
+    }
+
+    // Proves that the tapes v X u are closed under increments, for any X and for the parts v
+    // that occur, starting from the given one.
+    fn prove(&self, prefix: Vec<bool>) -> bool {
+        let mut pending = vec![prefix];
+        let mut proven: Vec<Vec<bool>> = Vec::new();
+        while let Some(prefix) = pending.pop() {
+            if proven.contains(&prefix) {
+                continue;
+            }
+            if proven.len() == COUNTER_MAX_PREFIXES {
+                return false;
+            }
+            for carry in [false, true] {
+                let Some(next) = self.increment(&prefix, carry) else {
+                    return false;
+                };
+                pending.push(next);
+            }
+            proven.push(prefix);
+        }
+        true
+    }
+}
+
+// Proves that the machine never halts, by finding a counter that it increments forever. The
+// digits are guessed from the least significant digits in two consecutive snapshots.
+fn counter(program: &TuringCountProgram, snapshots: &[TuringSnapshot]) -> bool {
+    let mut tried = HashSet::new();
+    for pair in snapshots.windows(2) {
+        let [first, second] = pair else {
+            continue;
+        };
+        if first.state != second.state {
+            continue;
+        }
+        for head in 1..=COUNTER_MAX_HEAD {
+            for len in 1..=COUNTER_MAX_DIGIT {
+                let digit = |tape: &[bool]| {
+                    let end = tape.len().checked_sub(head)?;
+                    Some(tape[end.checked_sub(len)?..end].to_vec())
+                };
+                let (Some(zero), Some(one)) = (digit(&first.tape), digit(&second.tape)) else {
+                    continue;
+                };
+                if zero == one {
+                    continue;
+                }
+
+                for digits in [[zero.clone(), one.clone()], [one, zero]] {
+                    let counter = Counter {
+                        program,
+                        state: first.state,
+                        head: first.tape[first.tape.len() - head..].to_vec(),
+                        digits,
+                    };
+                    let tape: Vec<Segment> = cells(&first.tape).collect();
+                    let Some(prefix) = counter.parse(&tape, false) else {
+                        continue;
+                    };
+                    let key = (counter.head.clone(), counter.digits.clone(), prefix.clone());
+                    if tried.insert(key) && counter.prove(prefix) {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+    false
+}
+
+// The symbol of the cell under the head, which also holds the state.
+fn head_symbol(state: usize, value: u8) -> u8 {
+    2 + 2 * state as u8 + value
+}
+
+// A set of configurations that contains the initial one, that is closed under the steps of the
+// machine, and where no configuration halts. A configuration is written as the cells of the
+// tape, the cell under the head being marked with the state, and belongs to the set if all its
+// windows of `width` consecutive cells do. The set is a regular language: the automaton that
+// recognizes it has the windows of width - 1 cells as states, and a transition from the first
+// width - 1 cells of each window to its last ones.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ClosedLanguage {
+    width: usize,
+    windows: BTreeSet<Vec<u8>>,
+}
+
+impl ClosedLanguage {
+    // Adds the windows of the successors until the set is closed, for increasing widths. Each
+    // round only looks at the configurations with a window that was added in the last one.
+    pub fn find(program: &TuringCountProgram) -> Option<Self> {
+        (2..=LANGUAGE_MAX_WIDTH).find_map(|width| {
+            let mut language = ClosedLanguage {
+                width,
+                windows: Self::initial(width),
+            };
+            let mut added = language.windows.clone();
+            while !added.is_empty() {
+                added = language.successors(program, &added)?;
+                language.windows.extend(added.iter().cloned());
+                if language.windows.len() > LANGUAGE_MAX_WINDOWS {
+                    return None;
+                }
+            }
+            Some(language)
+        })
+    }
+
+    // Checks the certificate, independently of the way it was found.
+    pub fn verify(&self, program: &TuringCountProgram) -> bool {
+        Self::initial(self.width).is_subset(&self.windows)
+            && self.successors(program, &self.windows).is_some_and(|next| next.is_empty())
+    }
+
+    // The windows of the initial configuration, with a blank tape.
+    fn initial(width: usize) -> BTreeSet<Vec<u8>> {
+        let mut windows = BTreeSet::from([vec![0; width]]);
+        for i in 0..width {
+            let mut window = vec![0; width];
+            window[i] = head_symbol(0, 0);
+            windows.insert(window);
+        }
+        windows
+    }
+
+    // The windows around the head after a step from the configurations of the language that
+    // contain one of the given windows, which are not in the language yet. Returns None if one
+    // of the configurations halts. The other windows are unchanged.
+    fn successors(
+        &self,
+        program: &TuringCountProgram,
+        windows: &BTreeSet<Vec<u8>>,
+    ) -> Option<BTreeSet<Vec<u8>>> {
+        let width = self.width;
+        let mut next = BTreeSet::new();
+        let mut visit = |segment: &[u8]| {
+            let head = segment[width] - 2;
+            let rule = program.rule(head as usize / 2, head as usize % 2);
+            if !rule.is_defined() || rule.new_state() == program.nstates() {
+                return false;
+            }
+            let mut segment = segment.to_vec();
+            segment[width] = rule.tape_value() as u8;
+            let position = if rule.move_right() { width + 1 } else { width - 1 };
+            segment[position] = head_symbol(rule.new_state(), segment[position]);
+            for window in segment.windows(width) {
+                if !self.windows.contains(window) {
+                    next.insert(window.to_vec());
+                }
+            }
+            true
+        };
+
+        // The segments of 2 * width + 1 cells with the head in the middle, which contain the
+        // window at a given offset.
+        let mut segment = vec![0; 2 * width + 1];
+        for window in windows {
+            for offset in 0..=width + 1 {
+                let head = (offset..offset + width).contains(&width);
+                if head != window.iter().any(|&symbol| symbol >= 2)
+                    || head && window[width - offset] < 2
+                {
+                    continue;
+                }
+                segment[offset..offset + width].copy_from_slice(window);
+                let nstates = program.nstates();
+                if !self.extend(nstates, &mut segment, offset, offset + width, &mut visit) {
+                    return None;
+                }
+            }
+        }
+        Some(next)
+    }
+
+    // Calls `visit` with the segments whose windows are all in the language, filling in the
+    // cells after `end` first, then the cells before `start`. Stops when `visit` returns false.
+    fn extend(
+        &self,
+        nstates: usize,
+        segment: &mut [u8],
+        start: usize,
+        end: usize,
+        visit: &mut impl FnMut(&[u8]) -> bool,
+    ) -> bool {
+        // The next cell, the window that it completes, and the filled cells.
+        let (position, window, filled) = if end < segment.len() {
+            (end, end + 1 - self.width..end + 1, (start, end + 1))
+        } else if start > 0 {
+            (start - 1, start - 1..start - 1 + self.width, (start - 1, end))
+        } else {
+            return visit(segment);
+        };
+        let symbols = if position == self.width { 2..2 + 2 * nstates as u8 } else { 0..2 };
+        for symbol in symbols {
+            segment[position] = symbol;
+            if self.windows.contains(&segment[window.clone()])
+                && !self.extend(nstates, segment, filled.0, filled.1, visit)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// The transitions of the automaton, as the windows, the cell under the head being written as
+// the state followed by its value.
+impl std::fmt::Display for ClosedLanguage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, window) in self.windows.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            for &symbol in window {
+                if symbol < 2 {
+                    write!(f, "{}", symbol)?;
+                } else {
+                    let state = (b'A' + (symbol - 2) / 2) as char;
+                    write!(f, "{}{}", state, (symbol - 2) % 2)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Recognizes the behaviour of a machine from the snapshots at the right end of the tape, and
+// proves that it never halts.
+pub fn decide(program: &TuringCountProgram, snapshots: &[TuringSnapshot]) -> Option<Behavior> {
+    if bouncer(program, snapshots) {
+        Some(Behavior::Bouncer)
+    } else if counter(program, snapshots) {
+        Some(Behavior::Counter)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tape(s: &str) -> Vec<bool> {
+        s.chars().map(|c| c == '1').collect()
+    }
+
+    #[test]
+    fn tapes() {
+        use Segment::{Cell, Repeat};
+
+        let blocks = repeated_blocks(&tape("010100"), &tape("01010100"));
+        assert_eq!(blocks, Some(vec![Cell(false), Repeat(tape("10"), 2), Cell(false)]));
+        let blocks = repeated_blocks(&tape("1110"), &tape("111110"));
+        assert_eq!(blocks, Some(vec![Cell(true), Repeat(tape("11"), 1), Cell(false)]));
+
+        // 0 1 0 (1 0)^(n+1) 1 0 0 = (0 1)^(n+3) 0 0
+        let mut segments: Vec<Segment> = cells(&tape("010")).collect();
+        segments.push(Repeat(tape("10"), 1));
+        segments.extend(cells(&tape("100")));
+        let normal = vec![Repeat(tape("01"), 3), Cell(false), Cell(false)];
+        assert_eq!(normalize(segments), normal);
+    }
+
+    #[test]
+    fn languages() {
+        let program: TuringCountProgram =
+            "A0:0>B A1:0<C B0:1<A B1:1>B C0:1>D C1:1>B D0:1>C D1:---".parse().unwrap();
+        let mut language = ClosedLanguage::find(&program).unwrap();
+        assert!(language.verify(&program));
+
+        // Without one of its windows, the language is no longer closed.
+        let window = language.windows.last().unwrap().clone();
+        language.windows.remove(&window);
+        assert!(!language.verify(&program));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::bit_tape::BitTape;
+use crate::def::{Behavior, ProgResult};
+use crate::turing_count::TuringCountProgram;
+use std::collections::HashMap;
+
+// The largest block of cells that the tape is divided into.
+const MACRO_MAX_WIDTH: usize = 8;
+
+// The run of a machine on a block of cells, from the moment the head enters it until it leaves
+// it, with the number of steps and of the entries into state 0.
+#[derive(Clone, Copy)]
+enum BlockRun {
+    Exit {
+        block: u32,
+        state: usize,
+        right: bool,
+        steps: usize,
+        output: u64,
+    },
+    Halt {
+        steps: usize,
+        output: u64,
+    },
+    // The machine reaches the undefined transition from the state on the symbol.
+    Undefined { state: usize, value: usize },
+    // The head never leaves the block.
+    Cycle,
+}
+
+// Runs a machine on a tape divided into blocks of the same width. Both sides of the tape are
+// compressed into runs of identical blocks, and the head passes a whole run at once when it
+// leaves each of its blocks in the same state and the same direction as it entered it. The
+// number of steps stays exact.
+#[derive(Clone)]
+pub struct TuringMacroMachine {
+    width: usize,
+    runs: HashMap<(usize, u32, bool), BlockRun>,
+    // The runs of blocks on the left and on the right of the head, from the furthest to the
+    // nearest one. All the blocks beyond them are blank.
+    tapes: [Vec<(u32, usize)>; 2],
+    // The head is about to enter the nearest block on the side it moves to.
+    state: usize,
+    right: bool,
+    step: usize,
+    output: u64,
+}
+
+impl TuringMacroMachine {
+    // Starts from a configuration with the head at the position `head` of the tape. The width of
+    // the blocks is the one that compresses the tape best. The blocks are read from the words of
+    // the tape.
+    pub fn new(state: usize, tape: &BitTape, head: isize, step: usize, output: u64) -> Self {
+        let (start, end) = (tape.start().min(head), tape.end().max(head + 1));
+        let compress = |width: usize| {
+            let block = |pos: isize| tape.cells(pos, width) as u32;
+            let w = width as isize;
+            // The head reads the first cell of the nearest block on the right. The blocks on the
+            // left end at the head, so the furthest one may reach beyond the accessed cells.
+            let right: Vec<u32> = (head..end).step_by(width).map(block).collect();
+            let left = (1..=(head - start + w - 1) / w).map(|i| block(head - i * w)).collect();
+            [left, right].map(|blocks: Vec<u32>| {
+                let mut runs: Vec<(u32, usize)> = Vec::new();
+                for &block in blocks.iter().rev() {
+                    push(&mut runs, block, 1);
+                }
+                if runs.first().is_some_and(|&(block, _)| block == 0) {
+                    runs.remove(0);
+                }
+                runs
+            })
+        };
+        let (width, tapes) = (1..=MACRO_MAX_WIDTH)
+            .map(|width| (width, compress(width)))
+            .min_by_key(|(_, tapes)| tapes[0].len() + tapes[1].len())
+            .unwrap();
+
+        TuringMacroMachine {
+            width,
+            runs: HashMap::new(),
+            tapes,
+            state,
+            right: true,
+            step,
+            output,
+        }
+    }
+
+    // Runs the program until it halts or reaches `max_steps`. Returns None at the limit, where
+    // the machine stays, so that it can run again with a larger limit.
+    pub fn run(
+        &mut self,
+        program: &TuringCountProgram,
+        max_steps: usize,
+    ) -> Option<ProgResult<u64>> {
+        loop {
+            let side = self.right as usize;
+            let (block, count) = self.tapes[side].last().copied().unwrap_or((0, usize::MAX));
+            match self.block_run(program, self.state, block, self.right) {
+                BlockRun::Exit { block: new_block, state, right, steps, output } => {
+                    // The head passes all the blocks of the run, with the same effect on each.
+                    let passes = if state == self.state && right == self.right { count } else { 1 };
+                    if passes == usize::MAX {
+                        return Some(ProgResult::NonHalting(Behavior::TranslatedCycler));
+                    }
+                    if steps.saturating_mul(passes) >= max_steps - self.step {
+                        return None;
+                    }
+                    self.step += steps * passes;
+                    self.output += output * passes as u64;
+
+                    if count == passes {
+                        self.tapes[side].pop();
+                    } else if count != usize::MAX {
+                        self.tapes[side].last_mut().unwrap().1 -= passes;
+                    }
+                    push(&mut self.tapes[!right as usize], new_block, passes);
+                    self.state = state;
+                    self.right = right;
+                }
+                BlockRun::Halt { steps, output } => {
+                    if steps >= max_steps - self.step {
+                        return None;
+                    }
+                    return Some(ProgResult::Out {
+                        output: self.output + output,
+                        steps: self.step + steps,
+                    });
+                }
+                BlockRun::Undefined { .. } => return Some(ProgResult::Timeout),
+                BlockRun::Cycle => return Some(ProgResult::NonHalting(Behavior::Cycler)),
+            }
+        }
+    }
+
+    // The undefined transition that the machine reaches next, after `run` returns a timeout.
+    pub fn undefined(&mut self, program: &TuringCountProgram) -> Option<(usize, usize)> {
+        let (block, _) = self.tapes[self.right as usize].last().copied().unwrap_or((0, 0));
+        match self.block_run(program, self.state, block, self.right) {
+            BlockRun::Undefined { state, value } => Some((state, value)),
+            _ => None,
+        }
+    }
+
+    // Runs the machine on a block that the head enters from the left when it moves right, or
+    // from the right.
+    fn block_run(
+        &mut self,
+        program: &TuringCountProgram,
+        state: usize,
+        block: u32,
+        right: bool,
+    ) -> BlockRun {
+        let width = self.width;
+        *self.runs.entry((state, block, right)).or_insert_with(|| {
+            let (mut state, mut block) = (state, block);
+            let mut position = if right { 0 } else { width as i32 - 1 };
+            let mut output = 0;
+            // The head can't stay in the block for longer without repeating a configuration.
+            let max_steps = (program.nstates() * width) << width;
+            for steps in 1..=max_steps {
+                let value = (block >> position & 1) as usize;
+                let rule = program.rule(state, value);
+                if !rule.is_defined() {
+                    return BlockRun::Undefined { state, value };
+                }
+                block = block & !(1 << position) | (rule.tape_value() as u32) << position;
+                state = rule.new_state();
+                if state == program.nstates() {
+                    return BlockRun::Halt { steps, output };
+                }
+                if state == 0 {
+                    output += 1;
+                }
+                position += if rule.move_right() { 1 } else { -1 };
+                if position < 0 || position >= width as i32 {
+                    let right = position >= 0;
+                    return BlockRun::Exit { block, state, right, steps, output };
+                }
+            }
+            BlockRun::Cycle
+        })
+    }
+}
+
+// Adds blocks next to the head, merging them with the nearest run if they are the same.
+fn push(runs: &mut Vec<(u32, usize)>, block: u32, count: usize) {
+    match runs.last_mut() {
+        Some((last, n)) if *last == block => *n += count,
+        _ => runs.push((block, count)),
+    }
+}